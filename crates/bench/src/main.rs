@@ -119,8 +119,7 @@ async fn run_ws(token: String, tx: tokio::sync::mpsc::UnboundedSender<(String, i
 
 async fn run_rest_from_redis(redis_url: String, token: String, tx: tokio::sync::mpsc::UnboundedSender<(String, i128)>) -> Result<()> {
     let client = redis::Client::open(redis_url.clone())?;
-    let conn = client.get_async_connection().await?;
-    let mut pubsub = conn.into_pubsub();
+    let mut pubsub = client.get_async_pubsub().await?;
     pubsub.subscribe("ob_updates").await?;
     use futures_util::StreamExt;
     use std::collections::HashSet;
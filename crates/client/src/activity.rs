@@ -0,0 +1,119 @@
+use poly_ob_common::settings::ChangeBiasConfig;
+use std::collections::HashMap;
+
+/// 未观测过的 token 的初始变化率：偏乐观，保证新 token 先被多抓几次再下结论
+const PRIOR: f64 = 0.5;
+
+/// 各 token 的变化率（每次抓取检测到变化的概率），由 Ack 中的 `changed` 指数平滑得到
+#[derive(Debug)]
+pub struct Activity {
+    alpha: f64,
+    floor: f64,
+    rate: HashMap<String, f64>,
+}
+
+impl Activity {
+    pub fn new(cfg: &ChangeBiasConfig) -> Self {
+        Self {
+            alpha: cfg.alpha.clamp(0.01, 1.0),
+            floor: 1000.0 / cfg.floor_refresh_ms.max(1) as f64,
+            rate: HashMap::new(),
+        }
+    }
+
+    /// 尽力而为 token 的保底刷新频率（次/s）
+    pub fn floor(&self) -> f64 {
+        self.floor
+    }
+
+    pub fn observe(&mut self, token: &str, changed: bool) {
+        let x = if changed { 1.0 } else { 0.0 };
+        let p = self.rate.entry(token.to_string()).or_insert(PRIOR);
+        *p += self.alpha * (x - *p);
+    }
+
+    pub fn score(&self, token: &str) -> f64 {
+        self.rate.get(token).copied().unwrap_or(PRIOR)
+    }
+
+    /// 移除不再追踪的 token
+    pub fn retain(&mut self, tokens: &[String]) {
+        let keep: std::collections::HashSet<&str> = tokens.iter().map(String::as_str).collect();
+        self.rate.retain(|t, _| keep.contains(t.as_str()));
+    }
+
+    /// 档位目标与保底之外的剩余预算按变化率分给各 token，使每次请求检测到的更新尽量多
+    pub fn bias(&self, tokens: &[String], rates: &mut [f64], budget: f64) {
+        let surplus = budget - rates.iter().sum::<f64>();
+        let scores: Vec<f64> = tokens.iter().map(|t| self.score(t)).collect();
+        let total: f64 = scores.iter().sum();
+        if surplus <= 0.0 || total <= 0.0 {
+            return;
+        }
+        for (r, s) in rates.iter_mut().zip(scores) {
+            *r += surplus * s / total;
+        }
+    }
+
+    /// 变化率不低于 `threshold` 的 token 数
+    pub fn active(&self, threshold: f64) -> usize {
+        self.rate.values().filter(|&&p| p >= threshold).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity() -> Activity {
+        Activity::new(&ChangeBiasConfig { alpha: 0.5, floor_refresh_ms: 4000, ..Default::default() })
+    }
+
+    fn tokens(ts: &[&str]) -> Vec<String> {
+        ts.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn observations_are_smoothed_from_the_prior() {
+        let mut a = activity();
+        assert_eq!(a.score("x"), PRIOR);
+        a.observe("x", true);
+        assert!((a.score("x") - 0.75).abs() < 1e-9);
+        a.observe("x", false);
+        a.observe("x", false);
+        assert!((a.score("x") - 0.1875).abs() < 1e-9);
+        assert_eq!(a.active(0.5), 0);
+        assert!((a.floor() - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn surplus_goes_to_changing_tokens() {
+        let mut a = activity();
+        for _ in 0..10 {
+            a.observe("hot", true);
+            a.observe("cold", false);
+        }
+        let ts = tokens(&["hot", "cold", "new"]);
+        let mut rates = vec![0.25; 3];
+        a.bias(&ts, &mut rates, 10.0);
+        assert!((rates.iter().sum::<f64>() - 10.0).abs() < 1e-9);
+        assert!(rates[0] > rates[2] && rates[2] > rates[1], "{:?}", rates);
+        assert!(rates[1] < 0.3, "不变的 token 只保留保底频率：{:?}", rates);
+        assert_eq!(a.active(0.5), 1);
+
+        // 没有剩余预算时不调整
+        let mut rates = vec![4.0; 3];
+        a.bias(&ts, &mut rates, 10.0);
+        assert_eq!(rates, [4.0; 3]);
+    }
+
+    #[test]
+    fn retain_drops_untracked_tokens() {
+        let mut a = activity();
+        a.observe("x", false);
+        a.observe("y", false);
+        a.retain(&tokens(&["y"]));
+        assert_eq!(a.score("x"), PRIOR);
+        assert!(a.score("y") < PRIOR);
+    }
+}
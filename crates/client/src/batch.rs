@@ -0,0 +1,159 @@
+use poly_ob_common::protocol::{Ack, AckStatus};
+use poly_ob_common::settings::BatchConfig;
+
+/// 一次时间片结果对批大小的含义
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// 成功且延迟在目标内：加性增
+    Healthy,
+    /// 超时（含超出指令时限）、5xx、429 或延迟超标：乘性减
+    Overloaded,
+    /// 413：乘性减，并把上限压到减后的大小；此后连续 `ceiling_recover_after` 次 `Healthy` 恢复上限
+    TooLarge,
+    /// 其他 4xx、限速拒绝等与批大小无关的结果
+    Neutral,
+}
+
+pub fn classify(res: &Result<Ack, String>, target_latency_ms: u64) -> Signal {
+    let ack = match res {
+        Ok(ack) => ack,
+        Err(_) => return Signal::Overloaded,
+    };
+    match (ack.status, ack.http_status) {
+        (AckStatus::Ok, _) if ack.latency_ms > target_latency_ms => Signal::Overloaded,
+        (AckStatus::Ok, _) => Signal::Healthy,
+        (AckStatus::DeadlineExceeded, _) => Signal::Overloaded,
+        (_, Some(413)) => Signal::TooLarge,
+        (_, Some(429)) => Signal::Overloaded,
+        (_, Some(s)) if s >= 500 => Signal::Overloaded,
+        (AckStatus::Error, None) => Signal::Overloaded,
+        _ => Signal::Neutral,
+    }
+}
+
+/// 单节点 AIMD 批大小控制器
+#[derive(Debug, Clone)]
+pub struct BatchController {
+    size: usize,
+    ceiling: usize,
+    /// 上限被 413 压低后连续 `Healthy` 的次数
+    streak: u32,
+    cfg: BatchConfig,
+}
+
+impl BatchController {
+    pub fn new(initial: usize, cfg: &BatchConfig) -> Self {
+        let min = cfg.min.max(1);
+        let ceiling = cfg.max.max(min);
+        Self { size: initial.clamp(min, ceiling), ceiling, streak: 0, cfg: cfg.clone() }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 按信号调整批大小，返回是否发生变化
+    pub fn observe(&mut self, signal: Signal) -> bool {
+        let min = self.cfg.min.max(1);
+        let before = self.size;
+        match signal {
+            Signal::Healthy => {
+                let max = self.cfg.max.max(min);
+                if self.ceiling < max {
+                    self.streak += 1;
+                    if self.streak >= self.cfg.ceiling_recover_after {
+                        self.ceiling = max;
+                        self.streak = 0;
+                    }
+                }
+                self.size = (self.size + self.cfg.increase).min(self.ceiling);
+            }
+            Signal::Overloaded => {
+                self.size = self.decreased(min);
+                self.streak = 0;
+            }
+            Signal::TooLarge => {
+                self.size = self.decreased(min);
+                self.ceiling = self.size;
+                self.streak = 0;
+            }
+            Signal::Neutral => {}
+        }
+        self.size != before
+    }
+
+    fn decreased(&self, min: usize) -> usize {
+        ((self.size as f64 * self.cfg.decrease).floor() as usize).max(min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> BatchConfig {
+        BatchConfig { min: 2, max: 100, increase: 5, decrease: 0.5, ceiling_recover_after: 3, ..Default::default() }
+    }
+
+    fn ack(status: AckStatus, http_status: Option<u16>, latency_ms: u64) -> Result<Ack, String> {
+        Ok(Ack { status, http_status, latency_ms, ..Ack::ok(1) })
+    }
+
+    #[test]
+    fn classify_maps_results_to_signals() {
+        assert_eq!(classify(&ack(AckStatus::Ok, Some(200), 100), 800), Signal::Healthy);
+        assert_eq!(classify(&ack(AckStatus::Ok, Some(200), 900), 800), Signal::Overloaded);
+        assert_eq!(classify(&ack(AckStatus::DeadlineExceeded, None, 0), 800), Signal::Overloaded);
+        assert_eq!(classify(&ack(AckStatus::Error, Some(429), 10), 800), Signal::Overloaded);
+        assert_eq!(classify(&ack(AckStatus::Error, Some(503), 10), 800), Signal::Overloaded);
+        assert_eq!(classify(&ack(AckStatus::Error, None, 10), 800), Signal::Overloaded);
+        assert_eq!(classify(&ack(AckStatus::Error, Some(413), 10), 800), Signal::TooLarge);
+        assert_eq!(classify(&ack(AckStatus::Error, Some(404), 10), 800), Signal::Neutral);
+        assert_eq!(classify(&Err("connection reset".into()), 800), Signal::Overloaded);
+    }
+
+    #[test]
+    fn fast_acks_increase_additively_up_to_max() {
+        let mut c = BatchController::new(90, &cfg());
+        assert!(c.observe(classify(&ack(AckStatus::Ok, Some(200), 10), 800)));
+        assert_eq!(c.size(), 95);
+        c.observe(Signal::Healthy);
+        assert_eq!(c.size(), 100);
+        assert!(!c.observe(Signal::Healthy));
+        assert_eq!(c.size(), 100);
+    }
+
+    #[test]
+    fn slow_acks_and_429_decrease_multiplicatively() {
+        let mut c = BatchController::new(40, &cfg());
+        c.observe(classify(&ack(AckStatus::Ok, Some(200), 900), 800));
+        assert_eq!(c.size(), 20);
+        c.observe(classify(&ack(AckStatus::Error, Some(429), 10), 800));
+        assert_eq!(c.size(), 10);
+        c.observe(Signal::Overloaded);
+        c.observe(Signal::Overloaded);
+        assert_eq!(c.size(), 2, "不低于 min");
+        assert!(!c.observe(Signal::Neutral));
+    }
+
+    #[test]
+    fn too_large_caps_ceiling_until_a_run_of_successes() {
+        let mut c = BatchController::new(40, &cfg());
+        c.observe(Signal::TooLarge);
+        assert_eq!(c.size(), 20);
+        c.observe(Signal::Healthy);
+        assert_eq!(c.size(), 20, "上限已压到 20");
+        // 中途过载会清零连续成功计数
+        c.observe(Signal::Overloaded);
+        assert_eq!(c.size(), 10);
+        c.observe(Signal::Healthy);
+        c.observe(Signal::Healthy);
+        assert_eq!(c.size(), 20);
+        c.observe(Signal::Healthy);
+        assert_eq!(c.size(), 25, "连续 3 次成功后上限恢复到 max");
+        c.observe(Signal::TooLarge);
+        assert_eq!(c.size(), 12);
+        c.observe(Signal::Healthy);
+        assert_eq!(c.size(), 12);
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::Result;
+use poly_ob_common::protocol::{read_frame, write_frame, Ack, Command, Request};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct Outgoing {
+    req: Request,
+    reply: oneshot::Sender<Ack>,
+}
+
+/// 到单个 Fetch 节点的长连接：多路复用，按请求 id 关联回执，断线后在下一次发送时重连
+pub struct NodeConn {
+    addr: String,
+    next_id: AtomicU64,
+    tx: mpsc::UnboundedSender<Outgoing>,
+}
+
+impl NodeConn {
+    pub fn spawn(addr: impl Into<String>) -> Self {
+        let addr = addr.into();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(conn_task(addr.clone(), rx));
+        Self { addr, next_id: AtomicU64::new(1), tx }
+    }
+
+    /// 发送指令并等待对应 Ack；超时、断线均以错误返回
+    pub async fn call(&self, cmd: Command, fence: Option<u64>, timeout: Duration) -> Result<Ack> {
+        self.request(cmd, fence, None, timeout).await
+    }
+
+    /// 发送抓取指令并携带时限：节点在 `deadline` 内未完成即放弃并回 `deadline_exceeded`；
+    /// 本端多等 `grace` 以覆盖传输耗时
+    pub async fn call_with_deadline(&self, cmd: Command, fence: Option<u64>, deadline: Duration, grace: Duration) -> Result<Ack> {
+        self.request(cmd, fence, Some(deadline.as_millis() as u64), deadline + grace).await
+    }
+
+    async fn request(&self, cmd: Command, fence: Option<u64>, deadline_ms: Option<u64>, timeout: Duration) -> Result<Ack> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Outgoing { req: Request { id, fence, deadline_ms, cmd }, reply })
+            .map_err(|_| anyhow::anyhow!("connection task for {} stopped", self.addr))?;
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(ack)) => Ok(ack),
+            Ok(Err(_)) => anyhow::bail!("connection to {} dropped before ack (id={})", self.addr, id),
+            Err(_) => anyhow::bail!("ack timeout from {} (id={})", self.addr, id),
+        }
+    }
+}
+
+/// 按地址复用 NodeConn，调度与健康检查共用同一条连接
+#[derive(Clone, Default)]
+pub struct ConnPool {
+    inner: Arc<Mutex<HashMap<String, Arc<NodeConn>>>>,
+}
+
+impl ConnPool {
+    pub fn get(&self, addr: &str) -> Arc<NodeConn> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entry(addr.to_string())
+            .or_insert_with(|| Arc::new(NodeConn::spawn(addr)))
+            .clone()
+    }
+
+    /// 丢弃不在 `addrs` 中的连接（连接任务在最后一个引用释放后退出）
+    pub fn retain(&self, addrs: &[String]) {
+        self.inner.lock().unwrap().retain(|a, _| addrs.contains(a));
+    }
+}
+
+async fn conn_task(addr: String, mut rx: mpsc::UnboundedReceiver<Outgoing>) {
+    loop {
+        // 空闲时不保持重连，等到有指令再建连
+        let Some(first) = rx.recv().await else { return };
+        let stream = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr)).await {
+            Ok(Ok(s)) => s,
+            Ok(Err(e)) => {
+                warn!("connect {} failed: {}", addr, e);
+                continue;
+            }
+            Err(_) => {
+                warn!("connect {} timed out", addr);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        info!("connected to fetch node {}", addr);
+        let (mut rd, mut wr) = stream.into_split();
+
+        // 读半部单独任务解析 Ack，避免在 select 中取消 read_exact
+        let (ack_tx, mut ack_rx) = mpsc::unbounded_channel::<Ack>();
+        let peer = addr.clone();
+        let reader = tokio::spawn(async move {
+            loop {
+                match read_frame::<_, Ack>(&mut rd).await {
+                    Ok(Some(ack)) => {
+                        if ack_tx.send(ack).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("read ack from {} failed: {}", peer, e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut pending: HashMap<u64, oneshot::Sender<Ack>> = HashMap::new();
+        let mut out = Some(first);
+        loop {
+            if let Some(o) = out.take() {
+                if let Err(e) = write_frame(&mut wr, &o.req).await {
+                    warn!("write to {} failed: {}", addr, e);
+                    break;
+                }
+                // 顺带清理调用方已超时放弃的条目
+                pending.retain(|_, r| !r.is_closed());
+                pending.insert(o.req.id, o.reply);
+            }
+            tokio::select! {
+                o = rx.recv() => match o {
+                    Some(o) => out = Some(o),
+                    None => {
+                        reader.abort();
+                        return;
+                    }
+                },
+                ack = ack_rx.recv() => match ack {
+                    Some(ack) => {
+                        if let Some(r) = pending.remove(&ack.id) {
+                            let _ = r.send(ack);
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+        reader.abort();
+        warn!("connection to {} lost, {} in-flight commands dropped", addr, pending.len());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::conn::ConnPool;
+use crate::plan::{NodeSpec, Topology};
+use poly_ob_common::protocol::Command;
+use poly_ob_common::redisx::RedisClient;
+use poly_ob_common::types::NodeRecord;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(1);
+/// 节点未响应 hello 时假定的容量
+const DEFAULT_CAPACITY: u32 = 20;
+
+/// 通过 hello 获取各节点声明的容量，未响应的节点按默认容量计
+pub async fn probe_nodes(pool: &ConnPool, addrs: &[String]) -> Vec<NodeSpec> {
+    let mut nodes = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let capacity_rps = match pool.get(addr).call(Command::Hello, None, Duration::from_secs(1)).await {
+            Ok(ack) => match ack.node {
+                Some(info) => {
+                    info!(node = %addr, node_id = %info.node_id, capacity_rps = info.capacity_rps, "node hello");
+                    info.capacity_rps
+                }
+                None => DEFAULT_CAPACITY,
+            },
+            Err(e) => {
+                warn!(node = %addr, "hello failed, assume {} rps: {}", DEFAULT_CAPACITY, e);
+                DEFAULT_CAPACITY
+            }
+        };
+        nodes.push(NodeSpec { addr: addr.clone(), capacity_rps });
+    }
+    nodes
+}
+
+/// 静态配置节点与注册表存活节点合并（同地址以注册表容量为准），按地址排序保证切分稳定
+pub fn merge_nodes(static_nodes: &[NodeSpec], live: &[NodeRecord]) -> Vec<NodeSpec> {
+    let mut nodes: Vec<NodeSpec> = static_nodes
+        .iter()
+        .filter(|n| !live.iter().any(|r| r.addr == n.addr))
+        .cloned()
+        .collect();
+    nodes.extend(live.iter().map(|r| NodeSpec { addr: r.addr.clone(), capacity_rps: r.capacity_rps }));
+    nodes.sort_by(|a, b| a.addr.cmp(&b.addr));
+    nodes
+}
+
+/// 替换拓扑中的节点集并打印增减，返回是否有变化
+pub fn apply_nodes(topo: &mut Topology, nodes: Vec<NodeSpec>) -> bool {
+    if topo.nodes == nodes {
+        return false;
+    }
+    for n in nodes.iter().filter(|n| !topo.nodes.contains(n)) {
+        info!(node = %n.addr, capacity_rps = n.capacity_rps, "node joined");
+    }
+    for n in topo.nodes.iter().filter(|n| !nodes.contains(n)) {
+        info!(node = %n.addr, capacity_rps = n.capacity_rps, "node left");
+    }
+    topo.nodes = nodes;
+    true
+}
+
+/// 轮询 Redis 注册表，节点加入/过期时更新拓扑，调度器据此重新切分。
+/// 静态节点经 `static_rx` 热加载，变化时立即重新合并
+pub async fn discovery_loop(
+    mut redis: RedisClient,
+    mut static_rx: watch::Receiver<Vec<NodeSpec>>,
+    topo_tx: watch::Sender<Topology>,
+) {
+    let mut tick = tokio::time::interval(DISCOVERY_INTERVAL);
+    let mut live = Vec::new();
+    let mut static_open = true;
+    loop {
+        tokio::select! {
+            _ = tick.tick() => match redis.live_nodes().await {
+                Ok(v) => live = v,
+                Err(e) => {
+                    // 注册表不可用时保持现有节点集，避免误判全部下线
+                    warn!("node discovery failed: {}", e);
+                    continue;
+                }
+            },
+            changed = static_rx.changed(), if static_open => {
+                if changed.is_err() {
+                    // 未启用热加载，静态节点不再变化
+                    static_open = false;
+                    continue;
+                }
+            }
+        }
+        let nodes = merge_nodes(&static_rx.borrow_and_update(), &live);
+        topo_tx.send_if_modified(|topo| apply_nodes(topo, nodes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(addr: &str, capacity_rps: u32) -> NodeSpec {
+        NodeSpec { addr: addr.into(), capacity_rps }
+    }
+
+    fn record(addr: &str, capacity_rps: u32) -> NodeRecord {
+        NodeRecord {
+            node_id: format!("n-{}", addr),
+            addr: addr.into(),
+            capacity_rps,
+            version: "0.1.0".into(),
+            started_at_ms: 0,
+            heartbeat_ms: 0,
+        }
+    }
+
+    #[test]
+    fn merge_prefers_registry_capacity_and_sorts_by_addr() {
+        let merged = merge_nodes(&[spec("c:1", 10), spec("a:1", 10)], &[record("c:1", 30), record("b:1", 20)]);
+        assert_eq!(merged, [spec("a:1", 10), spec("b:1", 20), spec("c:1", 30)]);
+        assert_eq!(merge_nodes(&[spec("a:1", 10)], &[]), [spec("a:1", 10)]);
+    }
+
+    #[test]
+    fn expired_registry_node_falls_back_to_static_entry() {
+        let live = [record("a:1", 50), record("b:1", 20)];
+        let mut topo = Topology { tokens: vec![], nodes: merge_nodes(&[spec("a:1", 10)], &live) };
+        assert!(!apply_nodes(&mut topo, merge_nodes(&[spec("a:1", 10)], &live)));
+        // 注册记录 TTL 过期后不再出现在 live 中：纯注册节点下线，静态节点恢复配置容量
+        assert!(apply_nodes(&mut topo, merge_nodes(&[spec("a:1", 10)], &[])));
+        assert_eq!(topo.nodes, [spec("a:1", 10)]);
+    }
+}
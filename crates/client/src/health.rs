@@ -0,0 +1,252 @@
+use crate::conn::ConnPool;
+use crate::plan::Topology;
+use poly_ob_common::protocol::{Ack, AckStatus, Command, NodeStatus};
+use poly_ob_common::settings::HealthConfig;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Default)]
+struct NodeHealth {
+    down: bool,
+    ok_streak: u32,
+    fail_streak: u32,
+    acks: VecDeque<bool>,
+}
+
+/// 节点健康状态：探测连续失败或 Ack 成功率过低即移出轮转，移出后连续探测成功才恢复
+#[derive(Debug)]
+pub struct HealthTracker {
+    cfg: HealthConfig,
+    nodes: HashMap<String, NodeHealth>,
+}
+
+/// Ack 是否说明节点本身可用：传输失败、节点本地错误（无上游状态码）视为不可用；
+/// 上游 4xx/5xx 与限速拒绝与节点健康无关
+pub fn ack_healthy(res: &Result<Ack, String>) -> bool {
+    match res {
+        Ok(ack) => !(matches!(ack.status, AckStatus::Error | AckStatus::DeadlineExceeded) && ack.http_status.is_none()),
+        Err(_) => false,
+    }
+}
+
+/// 应用层健康：Redis 可达，且近期上游请求没有以连接失败/超时告终。
+/// 只看 `recent_ms` 内的上游结果——被移出轮转的节点不再发请求，旧的失败不能让它永远出不来
+pub fn status_healthy(s: &NodeStatus, recent_ms: i64) -> Result<(), String> {
+    if !s.redis_ok {
+        return Err("redis unreachable".into());
+    }
+    if let Some(up) = &s.last_upstream {
+        let age = chrono::Utc::now().timestamp_millis() - up.at_ms;
+        if up.status.is_none() && age <= recent_ms {
+            return Err(format!("upstream unreachable: {}", up.error.as_deref().unwrap_or("")));
+        }
+    }
+    Ok(())
+}
+
+impl HealthTracker {
+    pub fn new(cfg: HealthConfig) -> Self {
+        Self { cfg, nodes: HashMap::new() }
+    }
+
+    /// 未见过的节点默认健康
+    pub fn is_healthy(&self, addr: &str) -> bool {
+        self.nodes.get(addr).map_or(true, |h| !h.down)
+    }
+
+    /// 仅保留健康节点的拓扑
+    pub fn filter(&self, topo: &Topology) -> Topology {
+        Topology {
+            tokens: topo.tokens.clone(),
+            nodes: topo.nodes.iter().filter(|n| self.is_healthy(&n.addr)).cloned().collect(),
+        }
+    }
+
+    pub fn retain(&mut self, topo: &Topology) {
+        self.nodes.retain(|addr, _| topo.nodes.iter().any(|n| &n.addr == addr));
+    }
+
+    /// 记录一次探测结果，返回健康状态是否翻转
+    pub fn record_probe(&mut self, addr: &str, ok: bool) -> bool {
+        let (down_after, up_after) = (self.cfg.down_after.max(1), self.cfg.up_after.max(1));
+        let h = self.nodes.entry(addr.to_string()).or_default();
+        if ok {
+            h.ok_streak += 1;
+            h.fail_streak = 0;
+            if h.down && h.ok_streak >= up_after {
+                h.down = false;
+                h.acks.clear();
+                info!(node = %addr, "node back in rotation after {} good probes", h.ok_streak);
+                return true;
+            }
+        } else {
+            h.fail_streak += 1;
+            h.ok_streak = 0;
+            if !h.down && h.fail_streak >= down_after {
+                h.down = true;
+                warn!(node = %addr, "node removed from rotation after {} failed probes", h.fail_streak);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// 记录一次 Ack 结果，返回健康状态是否翻转
+    pub fn record_ack(&mut self, addr: &str, ok: bool) -> bool {
+        let window = self.cfg.ack_window.max(1);
+        let h = self.nodes.entry(addr.to_string()).or_default();
+        if h.down {
+            return false;
+        }
+        h.acks.push_back(ok);
+        while h.acks.len() > window {
+            h.acks.pop_front();
+        }
+        if h.acks.len() < window {
+            return false;
+        }
+        let ratio = h.acks.iter().filter(|&&x| x).count() as f64 / h.acks.len() as f64;
+        if ratio < self.cfg.min_ack_success {
+            h.down = true;
+            h.ok_streak = 0;
+            warn!(node = %addr, ratio, "node removed from rotation on low ack success ratio");
+            return true;
+        }
+        false
+    }
+}
+
+/// 周期性以 `status` 指令探测拓扑中的全部节点（含已移出轮转的），结果回传调度器
+pub async fn health_loop(
+    cfg: HealthConfig,
+    pool: ConnPool,
+    topo_rx: watch::Receiver<Topology>,
+    probe_tx: mpsc::UnboundedSender<(String, bool)>,
+) {
+    let timeout = Duration::from_millis(cfg.probe_timeout_ms);
+    let recent_ms = (cfg.probe_interval_ms * 2) as i64;
+    loop {
+        let addrs = topo_rx.borrow().addrs();
+        for addr in addrs {
+            // 复用调度长连接；连接断开时 call 会触发重连
+            let verdict = match pool.get(&addr).call(Command::Status, None, timeout).await {
+                Ok(Ack { report: Some(s), .. }) => {
+                    debug!(
+                        node = %addr, node_id = %s.node_id, uptime_ms = s.uptime_ms, in_flight = s.in_flight,
+                        current_rps = s.current_rps, rate_usage = s.rate_usage, "health status"
+                    );
+                    status_healthy(&s, recent_ms)
+                }
+                Ok(_) => Err("status ack without report".to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            let ok = verdict.is_ok();
+            if let Err(reason) = verdict {
+                debug!(node = %addr, %reason, "health probe failed");
+            }
+            if probe_tx.send((addr, ok)).is_err() {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(cfg.probe_interval_ms)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{CyclePlan, NodeSpec};
+
+    fn tracker() -> HealthTracker {
+        HealthTracker::new(HealthConfig { down_after: 2, up_after: 3, ack_window: 4, min_ack_success: 0.5, ..HealthConfig::default() })
+    }
+
+    fn topo() -> Topology {
+        Topology {
+            tokens: (0..12).map(|i| format!("t{}", i)).collect(),
+            nodes: ["a:1", "b:1", "c:1"].iter().map(|a| NodeSpec { addr: a.to_string(), capacity_rps: 20 }).collect(),
+        }
+    }
+
+    #[test]
+    fn marked_down_after_consecutive_failed_probes() {
+        let mut h = tracker();
+        assert!(!h.record_probe("a:1", false));
+        assert!(h.is_healthy("a:1"));
+        // 中间一次成功清零失败计数
+        assert!(!h.record_probe("a:1", true));
+        assert!(!h.record_probe("a:1", false));
+        assert!(h.record_probe("a:1", false));
+        assert!(!h.is_healthy("a:1"));
+        assert!(!h.record_probe("a:1", false), "already down, no flip");
+        assert!(h.is_healthy("unknown:1"));
+    }
+
+    #[test]
+    fn down_node_slots_fail_over_to_the_rest() {
+        let mut h = tracker();
+        h.record_probe("b:1", false);
+        h.record_probe("b:1", false);
+        let active = h.filter(&topo());
+        assert_eq!(active.addrs(), ["a:1", "c:1"]);
+        assert_eq!(active.tokens.len(), 12);
+        let plan = CyclePlan::build(&active.tokens, &[], &active.caps(), &[2, 2]);
+        assert_eq!(plan.nodes.len(), 2);
+        let covered: usize = plan.nodes.iter().flat_map(|n| &n.batches).map(Vec::len).sum();
+        assert_eq!(covered, 12);
+    }
+
+    #[test]
+    fn reintroduced_after_enough_good_probes() {
+        let mut h = tracker();
+        h.record_probe("a:1", false);
+        h.record_probe("a:1", false);
+        assert!(!h.record_probe("a:1", true));
+        assert!(!h.record_probe("a:1", true));
+        assert!(!h.is_healthy("a:1"));
+        assert!(h.record_probe("a:1", true));
+        assert!(h.is_healthy("a:1"));
+        assert_eq!(h.filter(&topo()).nodes.len(), 3);
+    }
+
+    #[test]
+    fn low_ack_success_ratio_marks_down_once_window_is_full() {
+        let mut h = tracker();
+        for ok in [false, false, true] {
+            assert!(!h.record_ack("a:1", ok), "window not full yet");
+        }
+        assert!(h.record_ack("a:1", false));
+        assert!(!h.is_healthy("a:1"));
+        // 移出后的 Ack 不再计入，恢复只看探测
+        assert!(!h.record_ack("a:1", true));
+        for _ in 0..3 {
+            h.record_probe("a:1", true);
+        }
+        assert!(h.is_healthy("a:1"));
+        for ok in [true, false, true, false] {
+            assert!(!h.record_ack("a:1", ok), "ratio 0.5 is not below the minimum");
+        }
+    }
+
+    #[test]
+    fn retain_forgets_removed_nodes() {
+        let mut h = tracker();
+        h.record_probe("gone:1", false);
+        h.record_probe("gone:1", false);
+        h.retain(&topo());
+        assert!(h.is_healthy("gone:1"));
+    }
+
+    #[test]
+    fn upstream_errors_do_not_count_against_the_node() {
+        let ok = |status, http_status| Ok(Ack { status, http_status, ..Ack::ok(1) });
+        assert!(ack_healthy(&ok(AckStatus::Ok, None)));
+        assert!(ack_healthy(&ok(AckStatus::Error, Some(503))));
+        assert!(ack_healthy(&ok(AckStatus::RateLimited, None)));
+        assert!(!ack_healthy(&ok(AckStatus::Error, None)));
+        assert!(!ack_healthy(&ok(AckStatus::DeadlineExceeded, None)));
+        assert!(!ack_healthy(&Err("connection reset".into())));
+    }
+}
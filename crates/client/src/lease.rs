@@ -0,0 +1,167 @@
+use poly_ob_common::redisx::RedisClient;
+use poly_ob_common::settings::LeaderConfig;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// 本实例当前角色；未启用选主时恒为不带 fence 的 leader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Leader { fence: Option<u64> },
+    Standby,
+}
+
+impl Role {
+    pub fn is_leader(&self) -> bool {
+        matches!(self, Role::Leader { .. })
+    }
+
+    pub fn fence(&self) -> Option<u64> {
+        match self {
+            Role::Leader { fence } => *fence,
+            Role::Standby => None,
+        }
+    }
+}
+
+/// 租约循环：备机每 ttl/3 尝试抢占，leader 每 ttl/3 续期。
+/// 续期失败立即降级；Redis 不可达时在租约可能过期前主动降级，保证任一时刻至多一个 leader 在调度。
+/// `shutdown` 变为真（或发送端关闭）后，在当前 Redis 调用返回时退出，持有租约则先释放
+pub async fn leader_loop(mut redis: RedisClient, cfg: LeaderConfig, role_tx: watch::Sender<Role>, mut shutdown: watch::Receiver<bool>) {
+    let holder = format!(
+        "client-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    );
+    let ttl = Duration::from_millis(cfg.ttl_ms.max(300));
+    let tick = ttl / 3;
+    let mut last_renew = Instant::now();
+    info!(%holder, key = %cfg.key, ttl_ms = ttl.as_millis() as u64, "leader election enabled, standing by");
+    loop {
+        let role = *role_tx.borrow();
+        match role {
+            Role::Standby => match redis.acquire_lease(&cfg.key, &holder, ttl.as_millis() as u64).await {
+                Ok(Some(fence)) => {
+                    info!(fence, "acquired leader lease");
+                    last_renew = Instant::now();
+                    role_tx.send_replace(Role::Leader { fence: Some(fence) });
+                }
+                Ok(None) => debug!("lease held by another client, standing by"),
+                Err(e) => warn!("lease acquire failed: {}", e),
+            },
+            Role::Leader { fence } => match redis.renew_lease(&cfg.key, &holder, ttl.as_millis() as u64).await {
+                Ok(true) => last_renew = Instant::now(),
+                Ok(false) => {
+                    warn!(?fence, "leader lease lost, standing by");
+                    role_tx.send_replace(Role::Standby);
+                }
+                Err(e) => {
+                    warn!("lease renew failed: {}", e);
+                    if last_renew.elapsed() + tick >= ttl {
+                        warn!(?fence, "lease may have expired, stepping down");
+                        role_tx.send_replace(Role::Standby);
+                    }
+                }
+            },
+        }
+        if *shutdown.borrow() {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(tick) => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+    // 主动释放，备机无需等待 TTL 即可接管
+    if role_tx.borrow().is_leader() {
+        match redis.release_lease(&cfg.key, &holder).await {
+            Ok(()) => info!("leader lease released"),
+            Err(e) => warn!("lease release failed: {}", e),
+        }
+    }
+    role_tx.send_replace(Role::Standby);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::MockRedis;
+
+    struct Elector {
+        redis: MockRedis,
+        role: watch::Receiver<Role>,
+        shutdown: watch::Sender<bool>,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    /// ttl 300ms，即每 100ms 抢占或续期一次
+    async fn start(redis: MockRedis) -> Elector {
+        let cfg = LeaderConfig { enabled: true, ttl_ms: 300, ..Default::default() };
+        let client = RedisClient::connect(&redis.url).await.unwrap();
+        let (role_tx, role) = watch::channel(Role::Standby);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let task = tokio::spawn(leader_loop(client, cfg, role_tx, shutdown_rx));
+        Elector { redis, role, shutdown, task }
+    }
+
+    async fn wait_for(role: &mut watch::Receiver<Role>, f: impl FnMut(&Role) -> bool) -> Role {
+        *tokio::time::timeout(Duration::from_secs(2), role.wait_for(f)).await.expect("role did not change").unwrap()
+    }
+
+    #[tokio::test]
+    async fn acquires_renews_and_releases_on_shutdown() {
+        let mut e = start(MockRedis::start().await).await;
+        assert_eq!(wait_for(&mut e.role, Role::is_leader).await, Role::Leader { fence: Some(1) });
+        let holder = e.redis.holder().unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(e.redis.calls("lease:renew").len() >= 2);
+        assert_eq!(e.role.borrow().fence(), Some(1), "续期不改变 fence");
+
+        e.shutdown.send_replace(true);
+        tokio::time::timeout(Duration::from_secs(1), e.task).await.unwrap().unwrap();
+        assert_eq!(*e.role.borrow(), Role::Standby);
+        assert_eq!(e.redis.holder(), None);
+        assert_eq!(e.redis.calls("lease:release")[0][4], holder);
+    }
+
+    #[tokio::test]
+    async fn standby_takes_over_expired_lease_with_higher_fence() {
+        let redis = MockRedis::start().await;
+        redis.hold("other");
+        let mut e = start(redis).await;
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(*e.role.borrow(), Role::Standby);
+        assert!(e.redis.calls("lease:acquire").len() >= 2);
+
+        e.redis.expire();
+        assert_eq!(wait_for(&mut e.role, Role::is_leader).await, Role::Leader { fence: Some(2) });
+    }
+
+    #[tokio::test]
+    async fn steps_down_when_lease_is_lost_or_redis_is_down() {
+        let mut e = start(MockRedis::start().await).await;
+        wait_for(&mut e.role, Role::is_leader).await;
+        e.redis.hold("other");
+        wait_for(&mut e.role, |r| !r.is_leader()).await;
+
+        e.redis.expire();
+        assert_eq!(wait_for(&mut e.role, Role::is_leader).await, Role::Leader { fence: Some(3) });
+        // Redis 不可达：在租约可能过期前（300ms 内）主动降级
+        e.redis.set_down(true);
+        let start = Instant::now();
+        wait_for(&mut e.role, |r| !r.is_leader()).await;
+        assert!(start.elapsed() < Duration::from_millis(300), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn standby_shutdown_does_not_release() {
+        let redis = MockRedis::start().await;
+        redis.hold("other");
+        let e = start(redis).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(e.shutdown);
+        tokio::time::timeout(Duration::from_secs(1), e.task).await.unwrap().unwrap();
+        assert!(e.redis.calls("lease:release").is_empty());
+        assert_eq!(e.redis.holder().as_deref(), Some("other"));
+    }
+}
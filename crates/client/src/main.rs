@@ -1,97 +1,168 @@
+mod activity;
+mod batch;
+mod conn;
+mod discovery;
+mod health;
+mod lease;
+mod markets;
+mod plan;
+mod quarantine;
+mod reload;
+mod scheduler;
+mod tiers;
+#[cfg(test)]
+mod testutil;
+mod watchdog;
+
 use anyhow::Result;
+use conn::ConnPool;
+use lease::Role;
+use discovery::probe_nodes;
+use plan::{RollingPlan, Topology};
 use poly_ob_common::http::HttpClient;
 use poly_ob_common::redisx::RedisClient;
 use poly_ob_common::settings::{load_client, ClientConfig};
-use tokio::net::TcpStream;
-use tokio::io::AsyncWriteExt;
-use tokio::time::{sleep_until, Duration, Instant};
-use tracing::{error, info};
+use scheduler::{build_cycle, Scheduler};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const CONFIG_PATH: &str = "client_config.toml";
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cfg = load_client(CONFIG_PATH)?;
+    if std::env::args().any(|a| a == "--dump-plan") {
+        // stdout 只输出计划 JSON，日志走 stderr
+        tracing_subscriber::fmt().with_env_filter("info").with_writer(std::io::stderr).init();
+        return dump_plan(&cfg).await;
+    }
     tracing_subscriber::fmt().with_env_filter("info").init();
-    let cfg = load_client("client_config.toml")?;
     run_client(cfg).await
 }
 
+/// 打印覆盖 `plan_horizon_secs` 的派发计划（JSON）后退出，用于上线前核对调度
+async fn dump_plan(cfg: &ClientConfig) -> Result<()> {
+    let pool = ConnPool::default();
+    let mut nodes = probe_nodes(&pool, &cfg.fetch_nodes).await;
+    let mut set_tokens = Vec::new();
+    if cfg.node_discovery || cfg.reload.token_set.is_some() {
+        let mut redis = RedisClient::connect(&cfg.redis_url).await?;
+        if cfg.node_discovery {
+            nodes = discovery::merge_nodes(&nodes, &redis.live_nodes().await?);
+        }
+        if let Some(key) = &cfg.reload.token_set {
+            set_tokens = redis.token_set(key).await?;
+        }
+    }
+    let market_tokens = match cfg.markets.enabled {
+        true => HttpClient::new(&cfg.base_url)?.discover_tokens(&cfg.markets.filter).await?,
+        false => Vec::new(),
+    };
+    let topo = Topology { tokens: reload::merge_tokens(&[&cfg.tracked_tokens(), &set_tokens, &market_tokens]), nodes };
+    let tiers = tiers::TierSet::from_config(cfg);
+    let cycle = build_cycle(&topo, &tiers, None, &cfg.batch, &mut HashMap::new());
+    let report = tiers.report(&cycle, &topo.tokens);
+    let plan = RollingPlan::new(cycle, topo.addrs(), Duration::from_secs(cfg.plan_horizon_secs), Instant::now());
+    let mut dump = plan.dump();
+    dump.tiers = report;
+    println!("{}", serde_json::to_string_pretty(&dump)?);
+    Ok(())
+}
+
 async fn run_client(cfg: ClientConfig) -> Result<()> {
     let mut redis = RedisClient::connect(&cfg.redis_url).await?;
-    let http = HttpClient::new(&cfg.base_url)?;
-    info!("client started, tokens={}, nodes={}", cfg.tokens.len(), cfg.fetch_nodes.len());
-
-    // health check loop for fetch nodes
-    tokio::spawn(health_loop(cfg.fetch_nodes.clone()));
-
-    // scheduler loop
-    scheduler_loop(cfg, http, &mut redis).await
-}
+    info!("client started, tokens={}, nodes={}", cfg.tracked_tokens().len(), cfg.fetch_nodes.len());
 
-async fn health_loop(nodes: Vec<String>) {
-    loop {
-        for n in &nodes {
-            // TCP zero-RTT is not possible, but we can keepalive or pre-connect from fetcher side.
-            // Here we just attempt connect to check liveness quickly (short timeout)
-            let addr = n.clone();
-            let fut = TcpStream::connect(&addr);
-            let res = tokio::time::timeout(Duration::from_millis(200), fut).await;
-            match res {
-                Ok(Ok(mut s)) => {
-                    // immediate close after connect is enough for health
-                    let _ = s.shutdown().await;
-                    info!("health ok: {}", addr);
-                }
-                _ => {
-                    error!("health fail: {}", addr);
-                }
+    // 拓扑（tokens/节点）变化经 watch 通知调度器重建计划
+    let pool = ConnPool::default();
+    let static_nodes = probe_nodes(&pool, &cfg.fetch_nodes).await;
+    let mut nodes = static_nodes.clone();
+    if cfg.node_discovery {
+        match redis.live_nodes().await {
+            Ok(live) => nodes = discovery::merge_nodes(&static_nodes, &live),
+            Err(e) => warn!("initial node discovery failed: {}", e),
+        }
+    }
+    let mut set_tokens = Vec::new();
+    if let Some(key) = &cfg.reload.token_set {
+        match redis.token_set(key).await {
+            Ok(v) => set_tokens = v,
+            Err(e) => warn!(%key, "initial token set read failed: {}", e),
+        }
+    }
+    // 市场发现：启动时先拉取一轮，之后由 market_loop 定期刷新
+    let (market_tx, market_rx) = watch::channel(Vec::new());
+    if cfg.markets.enabled {
+        let http = HttpClient::new(&cfg.base_url)?;
+        match http.discover_tokens(&cfg.markets.filter).await {
+            Ok(tokens) => {
+                info!(tokens = tokens.len(), "market discovery done");
+                market_tx.send_replace(tokens);
             }
+            Err(e) => warn!("initial market discovery failed: {}", e),
         }
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        tokio::spawn(markets::market_loop(http, cfg.markets.clone(), market_tx));
+    }
+    let topo = Topology { tokens: reload::merge_tokens(&[&cfg.tracked_tokens(), &set_tokens, &market_rx.borrow()]), nodes };
+    let (topo_tx, topo_rx) = watch::channel(topo.clone());
+    // 静态节点热加载后，开启发现时交给发现循环与注册表重新合并
+    let (static_tx, static_rx) = watch::channel(static_nodes.clone());
+    let static_tx = cfg.node_discovery.then_some(static_tx);
+    if cfg.node_discovery {
+        tokio::spawn(discovery::discovery_loop(redis.clone(), static_rx, topo_tx.clone()));
+    }
+    let (tiers_tx, tiers_rx) = watch::channel(tiers::TierSet::from_config(&cfg));
+    if cfg.reload.watch_config || cfg.reload.token_set.is_some() || cfg.markets.enabled {
+        let reloader = reload::Reloader::new(CONFIG_PATH, &cfg, redis.clone(), pool.clone(), static_nodes, static_tx, topo_tx.clone())
+            .with_markets(market_rx)
+            .with_tiers(tiers_tx);
+        tokio::spawn(reloader.run());
     }
-}
-
-async fn scheduler_loop(cfg: ClientConfig, http: HttpClient, redis: &mut RedisClient) -> Result<()> {
-    let n = cfg.fetch_nodes.len().max(1);
-    let delta = Duration::from_secs_f64(1.0 / (20.0 * n as f64)); // 全局节拍
-    let mut next = Instant::now() + Duration::from_millis(200);
 
-    // 每个节点维护一个最近 payload，初始为全量 tokens
-    let mut last_payload: Vec<Vec<String>> = vec![cfg.tokens.clone(); n];
+    // health check loop for fetch nodes：探测结果回传调度器，不健康节点移出轮转
+    let (probe_tx, probe_rx) = mpsc::unbounded_channel();
+    tokio::spawn(health::health_loop(cfg.health.clone(), pool.clone(), topo_rx.clone(), probe_tx));
 
-    let batch = cfg.tokens.len().max(1) / (20 * n) + 1; // 初始批大小，后续可自适应
+    // 选主：未启用时本实例恒为 leader；启用后以备机身份起步，抢到租约才开始调度
+    let (role_tx, role_rx) = watch::channel(if cfg.leader.enabled { Role::Standby } else { Role::Leader { fence: None } });
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let leader_task = cfg.leader.enabled.then(|| tokio::spawn(lease::leader_loop(redis.clone(), cfg.leader.clone(), role_tx, shutdown_rx)));
+    // 退出时最多等一个 TTL 让租约循环释放租约，超过后租约本就会自然过期
+    let release_wait = Duration::from_millis(cfg.leader.ttl_ms);
 
-    loop {
-        for (i, node) in cfg.fetch_nodes.iter().enumerate() {
-            // 计算该槽位分配的批（简单轮询切片）
-            let offset = (chrono::Utc::now().timestamp() as usize + i) % cfg.tokens.len().max(1);
-            let mut lump = Vec::with_capacity(batch);
-            for k in 0..batch {
-                lump.push(cfg.tokens[(offset + k) % cfg.tokens.len()].clone());
+    // 隔离表：Fetch 对分定位出的坏 token 不再进入计划，到期后放回复检
+    let (quarantine_tx, quarantine_rx) = watch::channel(HashSet::new());
+    if cfg.quarantine.enabled {
+        match redis.quarantined().await {
+            Ok(v) => {
+                quarantine_tx.send_replace(v.into_iter().map(|r| r.token_id).collect());
             }
-            last_payload[i] = lump.clone();
+            Err(e) => warn!("initial quarantine read failed: {}", e),
+        }
+        let q = quarantine::quarantine_loop(redis.clone(), cfg.quarantine.clone(), role_rx.clone(), quarantine_tx);
+        tokio::spawn(q);
+    }
 
-            // 发送指令（TCP socket），消息格式: JSON { tokens: [...], trigger: true }
-            let payload = serde_json::json!({ "tokens": lump, "trigger": true });
-            if let Err(e) = send_command(node, &payload).await {
-                error!("send to {} failed: {}", node, e);
-            }
+    // 陈旧巡检：告警发布到 Redis 频道，陈旧 token 插队到接下来的派发
+    let (urgent_tx, urgent_rx) = mpsc::unbounded_channel();
+    if cfg.watchdog.enabled {
+        let wd = watchdog::watchdog_loop(redis.clone(), cfg.watchdog.clone(), topo_rx.clone(), role_rx.clone(), quarantine_rx.clone(), urgent_tx);
+        tokio::spawn(wd);
+    }
 
-            // 全局节拍间隔
-            sleep_until(next).await;
-            next += delta;
+    // scheduler loop
+    tokio::select! {
+        r = Scheduler::new(cfg, pool, topo).run(topo_rx, probe_rx, role_rx, urgent_rx, quarantine_rx, tiers_rx) => r,
+        _ = tokio::signal::ctrl_c() => {
+            // 通知租约循环退出，等它释放租约后再退出
+            shutdown_tx.send_replace(true);
+            if let Some(t) = leader_task {
+                let _ = tokio::time::timeout(release_wait, t).await;
+            }
+            info!("client shutting down");
+            Ok(())
         }
     }
 }
-
-async fn send_command(addr: &str, json: &serde_json::Value) -> Result<()> {
-    use tokio::io::AsyncWriteExt;
-    let mut stream = TcpStream::connect(addr).await?;
-    let data = serde_json::to_vec(json)?;
-    // 简单定界：长度前缀 + 数据
-    let len = (data.len() as u32).to_be_bytes();
-    stream.write_all(&len).await?;
-    stream.write_all(&data).await?;
-    stream.shutdown().await?;
-    Ok(())
-}
-
-
@@ -0,0 +1,29 @@
+use poly_ob_common::http::HttpClient;
+use poly_ob_common::settings::MarketDiscoveryConfig;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+/// 定期翻页拉取 CLOB 市场列表，满足过滤条件的 tokens 经 watch 交给热加载合并进拓扑。
+/// 拉取失败时保持上一次的结果，避免误删全部 token
+pub async fn market_loop(http: HttpClient, cfg: MarketDiscoveryConfig, tokens_tx: watch::Sender<Vec<String>>) {
+    let mut tick = tokio::time::interval(Duration::from_secs(cfg.interval_secs.max(10)));
+    // 首轮结果已在启动时拉取
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        match http.discover_tokens(&cfg.filter).await {
+            Ok(tokens) => {
+                tokens_tx.send_if_modified(|cur| {
+                    if *cur == tokens {
+                        return false;
+                    }
+                    info!(tokens = tokens.len(), "market discovery updated");
+                    *cur = tokens;
+                    true
+                });
+            }
+            Err(e) => warn!("market discovery failed: {}", e),
+        }
+    }
+}
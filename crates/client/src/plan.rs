@@ -0,0 +1,478 @@
+use crate::tiers::TierReport;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use tokio::time::{Duration, Instant};
+
+/// 单个节点在一个周期内的工作：按顺序轮询自己的 batches；token 在一个周期内出现的次数即其权重
+#[derive(Debug, Clone)]
+pub struct NodePlan {
+    pub batches: Vec<Vec<String>>,
+    pub rps: f64,
+}
+
+impl NodePlan {
+    /// 该节点一个周期的时长（= 批数 / 节点速率）
+    pub fn cycle(&self) -> Duration {
+        if self.batches.is_empty() || self.rps <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.batches.len() as f64 / self.rps)
+    }
+}
+
+/// 周期计划：tokens 按负载切分给节点，再按批大小切成 batches；时间片依 `order` 轮流分给各节点
+#[derive(Debug, Clone)]
+pub struct CyclePlan {
+    pub nodes: Vec<NodePlan>,
+    /// 一轮内各时间片依次对应的节点下标
+    pub order: Vec<usize>,
+    /// 全局时间片间隔 Δ
+    pub slot_interval: Duration,
+    /// 名义 token 刷新吞吐（token/s）= Σ 节点容量 × 批大小
+    pub token_rate: f64,
+    /// token → (节点下标, 一个周期内出现的次数)
+    owner: HashMap<String, (usize, u32)>,
+}
+
+/// 调度游标：记录轮内位置与各节点下一批的下标
+#[derive(Debug, Clone, Default)]
+pub struct Cursor {
+    slot: usize,
+    batch: Vec<usize>,
+}
+
+/// 初始批大小：让每个 token 约每秒刷新一次（集群总速率 = 各节点容量之和）
+pub fn default_batch(tokens: usize, total_rps: u32) -> usize {
+    let rate = total_rps.max(1) as f64;
+    ((tokens.max(1) as f64 / rate).ceil() as usize).max(1)
+}
+
+impl CyclePlan {
+    /// `caps` 为各节点容量（req/s）：tokens 按负载（权重之和）与容量成比例分给节点，时间片按容量加权轮转；
+    /// `weights` 为各 token 一个周期内的刷新次数（缺省 1），`batches` 为各节点的批大小
+    pub fn build(tokens: &[String], weights: &[u32], caps: &[u32], batches: &[usize]) -> Self {
+        let total: u32 = caps.iter().sum();
+        let weights: Vec<u32> = (0..tokens.len()).map(|i| weights.get(i).copied().unwrap_or(1).max(1)).collect();
+        let mut plans = Vec::with_capacity(caps.len());
+        let mut owner = HashMap::with_capacity(tokens.len());
+        let mut token_rate = 0.0;
+        let mut start = 0;
+        for (i, len) in split_by_load(&weights, caps).into_iter().enumerate() {
+            let share = &tokens[start..start + len];
+            let batch = batches.get(i).copied().unwrap_or(1).max(1);
+            let batches = spread_batches(share, &weights[start..start + len], batch);
+            for b in &batches {
+                for t in b {
+                    owner.entry(t.clone()).or_insert((i, 0)).1 += 1;
+                }
+            }
+            start += len;
+            token_rate += caps[i] as f64 * batch as f64;
+            plans.push(NodePlan { batches, rps: caps[i] as f64 });
+        }
+        let slot_interval = Duration::from_secs_f64(1.0 / total.max(1) as f64);
+        Self { nodes: plans, order: weighted_order(caps), slot_interval, token_rate, owner }
+    }
+
+    /// token 的期望刷新间隔（节点周期 / 出现次数）；未被计划覆盖时返回 `None`
+    pub fn refresh_interval(&self, token: &str) -> Option<Duration> {
+        self.owner.get(token).map(|&(i, n)| self.nodes[i].cycle() / n.max(1))
+    }
+
+    /// 全部 token 中最长的刷新间隔
+    pub fn max_refresh_interval(&self) -> Duration {
+        self.owner.values().map(|&(i, n)| self.nodes[i].cycle() / n.max(1)).max().unwrap_or(Duration::ZERO)
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        Cursor { slot: 0, batch: vec![0; self.nodes.len()] }
+    }
+
+    /// 取下一个时间片：返回 (节点下标, 本片的 batch)；节点无任务时 batch 为 `None`
+    pub fn next_slot<'a>(&'a self, cur: &mut Cursor) -> (usize, Option<&'a [String]>) {
+        if cur.batch.len() != self.nodes.len() {
+            *cur = self.cursor();
+        }
+        if self.order.is_empty() {
+            return (0, None);
+        }
+        let node = self.order[cur.slot % self.order.len()];
+        cur.slot = (cur.slot + 1) % self.order.len();
+        let np = &self.nodes[node];
+        if np.batches.is_empty() {
+            return (node, None);
+        }
+        let b = cur.batch[node] % np.batches.len();
+        cur.batch[node] = (b + 1) % np.batches.len();
+        (node, Some(np.batches[b].as_slice()))
+    }
+}
+
+/// 按容量比例切分连续的 token 区间（保持原顺序），使各节点的负载（token 权重之和）与容量成比例；
+/// 返回各节点分到的 token 数
+fn split_by_load(loads: &[u32], caps: &[u32]) -> Vec<usize> {
+    let total_cap: u64 = caps.iter().map(|&c| c as u64).sum();
+    if total_cap == 0 {
+        return vec![0; caps.len()];
+    }
+    let total_load: u64 = loads.iter().map(|&w| w as u64).sum();
+    let mut lens = vec![0; caps.len()];
+    let (mut node, mut cap_before, mut load_before) = (0, 0u64, 0u64);
+    for &w in loads {
+        // token 负载的中点落在哪个节点的容量区间内就归哪个节点
+        let mid = 2 * load_before + w as u64;
+        while node + 1 < caps.len() && mid * total_cap >= 2 * total_load * (cap_before + caps[node] as u64) {
+            cap_before += caps[node] as u64;
+            node += 1;
+        }
+        lens[node] += 1;
+        load_before += w as u64;
+    }
+    lens
+}
+
+/// 将节点分到的 tokens 按权重展开为若干批：权重为 m 的 token 在周期内出现 m 次且尽量等距；
+/// 同一批内不重复，批数 = ⌈Σm / batch⌉，各批大小相差不超过 1
+fn spread_batches(tokens: &[String], weights: &[u32], batch: usize) -> Vec<Vec<String>> {
+    let n = tokens.len();
+    // 每个副本在周期内的理想位置；相位按 token 下标错开，权重全为 1 时即原顺序
+    let mut copies: Vec<(f64, usize)> = Vec::with_capacity(weights.iter().map(|&m| m as usize).sum());
+    for (i, &m) in weights.iter().enumerate() {
+        for j in 0..m {
+            copies.push(((j as f64 + i as f64 / n as f64) / m as f64, i));
+        }
+    }
+    copies.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let total = copies.len();
+    let k = total.div_ceil(batch);
+    let mut pending = copies.into_iter().map(|(_, i)| i);
+    // 与当前批重复的副本顺延到下一批
+    let mut carry: VecDeque<usize> = VecDeque::new();
+    let mut out: Vec<Vec<String>> = Vec::with_capacity(k);
+    for j in 0..k {
+        let size = total / k + usize::from(j < total % k);
+        let mut b: Vec<usize> = Vec::with_capacity(size);
+        let mut deferred = VecDeque::new();
+        while b.len() < size {
+            let Some(i) = carry.pop_front().or_else(|| pending.next()) else { break };
+            if b.contains(&i) {
+                deferred.push_back(i);
+            } else {
+                b.push(i);
+            }
+        }
+        carry.extend(deferred);
+        if !b.is_empty() {
+            out.push(b.into_iter().map(|i| tokens[i].clone()).collect());
+        }
+    }
+    // 仍无处安放的副本（权重超过批数）直接丢弃，token 的实际次数以计划为准
+    out
+}
+
+/// 平滑加权轮转：一轮长度 = Σ(容量/gcd)，各节点的时间片在轮内尽量均匀分布
+fn weighted_order(caps: &[u32]) -> Vec<usize> {
+    let g = caps.iter().copied().filter(|&c| c > 0).fold(0, gcd);
+    if g == 0 {
+        return Vec::new();
+    }
+    let w: Vec<i64> = caps.iter().map(|&c| (c / g) as i64).collect();
+    let total: i64 = w.iter().sum();
+    let mut cur = vec![0i64; w.len()];
+    let mut order = Vec::with_capacity(total as usize);
+    for _ in 0..total {
+        for (c, wi) in cur.iter_mut().zip(&w) {
+            *c += wi;
+        }
+        let pick = (0..w.len()).max_by_key(|&i| (cur[i], std::cmp::Reverse(i))).unwrap_or(0);
+        cur[pick] -= total;
+        order.push(pick);
+    }
+    order
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// 节点地址与其声明的容量
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeSpec {
+    pub addr: String,
+    pub capacity_rps: u32,
+}
+
+/// 调度输入：当前追踪的 tokens 与可用节点
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Topology {
+    pub tokens: Vec<String>,
+    pub nodes: Vec<NodeSpec>,
+}
+
+impl Topology {
+    pub fn addrs(&self) -> Vec<String> {
+        self.nodes.iter().map(|n| n.addr.clone()).collect()
+    }
+
+    pub fn caps(&self) -> Vec<u32> {
+        self.nodes.iter().map(|n| n.capacity_rps).collect()
+    }
+}
+
+/// 滚动计划中的一条派发：哪个节点在哪个时刻抓哪一批
+#[derive(Debug, Clone, Serialize)]
+pub struct Dispatch {
+    pub slot: u64,
+    pub at_ms: i64,
+    pub node: String,
+    pub tokens: Vec<String>,
+    #[serde(skip)]
+    pub at: Instant,
+}
+
+/// 预先展开 `horizon` 时长的派发序列；拓扑变化时从下一个未派发时刻起重建
+#[derive(Debug)]
+pub struct RollingPlan {
+    cycle: CyclePlan,
+    nodes: Vec<String>,
+    horizon: Duration,
+    cursor: Cursor,
+    origin: Instant,
+    origin_ms: i64,
+    /// 自 origin 起已展开的时间片数
+    planned: u64,
+    /// 全局时间片编号基准（重建后连续递增）
+    slot_base: u64,
+    entries: VecDeque<Dispatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanDump<'a> {
+    pub generated_at_ms: i64,
+    pub horizon_secs: f64,
+    pub slot_interval_ms: f64,
+    pub max_refresh_interval_ms: f64,
+    /// 各刷新档位的目标与可达间隔（由调用方填入）
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tiers: Vec<TierReport>,
+    pub dispatches: &'a VecDeque<Dispatch>,
+}
+
+impl RollingPlan {
+    pub fn new(cycle: CyclePlan, nodes: Vec<String>, horizon: Duration, start: Instant) -> Self {
+        let origin_ms = epoch_ms_at(start);
+        let cursor = cycle.cursor();
+        let mut plan = Self {
+            cycle,
+            nodes,
+            horizon,
+            cursor,
+            origin: start,
+            origin_ms,
+            planned: 0,
+            slot_base: 0,
+            entries: VecDeque::new(),
+        };
+        plan.fill(start);
+        plan
+    }
+
+    /// 展开时间片直到覆盖 `now + horizon`
+    pub fn fill(&mut self, now: Instant) {
+        let until = now.max(self.origin) + self.horizon;
+        // 没有任何 batch 时不展开，避免空转
+        if self.cycle.nodes.iter().all(|n| n.batches.is_empty()) {
+            return;
+        }
+        loop {
+            let at = self.slot_at(self.planned);
+            if at > until {
+                break;
+            }
+            let slot = self.slot_base + self.planned;
+            self.planned += 1;
+            let (idx, batch) = self.cycle.next_slot(&mut self.cursor);
+            let (Some(batch), Some(node)) = (batch, self.nodes.get(idx)) else { continue };
+            let offset_ms = (at - self.origin).as_millis() as i64;
+            self.entries.push_back(Dispatch {
+                slot,
+                at_ms: self.origin_ms + offset_ms,
+                node: node.clone(),
+                tokens: batch.to_vec(),
+                at,
+            });
+        }
+    }
+
+    pub fn peek(&self) -> Option<&Dispatch> {
+        self.entries.front()
+    }
+
+    pub fn pop(&mut self) -> Option<Dispatch> {
+        self.entries.pop_front()
+    }
+
+    /// 替换周期计划；保持节拍，从最早的未派发时刻（或 `now`）重新展开
+    pub fn rebuild(&mut self, cycle: CyclePlan, nodes: Vec<String>, now: Instant) {
+        let start = self.entries.front().map(|d| d.at).unwrap_or(now).max(now);
+        self.slot_base += self.planned;
+        self.cursor = cycle.cursor();
+        self.cycle = cycle;
+        self.nodes = nodes;
+        self.origin = start;
+        self.origin_ms = epoch_ms_at(start);
+        self.planned = 0;
+        self.entries.clear();
+        self.fill(now);
+    }
+
+    pub fn dump(&self) -> PlanDump<'_> {
+        PlanDump {
+            generated_at_ms: chrono::Utc::now().timestamp_millis(),
+            horizon_secs: self.horizon.as_secs_f64(),
+            slot_interval_ms: self.cycle.slot_interval.as_secs_f64() * 1000.0,
+            max_refresh_interval_ms: self.cycle.max_refresh_interval().as_secs_f64() * 1000.0,
+            tiers: Vec::new(),
+            dispatches: &self.entries,
+        }
+    }
+
+    fn slot_at(&self, k: u64) -> Instant {
+        // 以 origin 为基准按乘法计算，避免累加误差导致节拍漂移
+        self.origin + self.cycle.slot_interval.mul_f64(k as f64)
+    }
+}
+
+/// 将单调时钟时刻换算为墙钟毫秒（仅用于展示/导出）
+fn epoch_ms_at(at: Instant) -> i64 {
+    let now = Instant::now();
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    if at >= now {
+        now_ms + (at - now).as_millis() as i64
+    } else {
+        now_ms - (now - at).as_millis() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("t{}", i)).collect()
+    }
+
+    fn counts(plan: &CyclePlan) -> HashMap<&str, usize> {
+        let mut seen = HashMap::new();
+        for b in plan.nodes.iter().flat_map(|n| &n.batches) {
+            for t in b {
+                *seen.entry(t.as_str()).or_insert(0) += 1;
+            }
+        }
+        seen
+    }
+
+    // (token 数, 节点容量, 各节点批大小)
+    const CASES: &[(usize, &[u32], &[usize])] = &[
+        (1, &[20], &[1]),
+        (10, &[20], &[3]),
+        (100, &[20, 20], &[7, 7]),
+        (101, &[10, 30], &[5, 9]),
+        (1000, &[20, 40, 5], &[50, 13, 1]),
+        (7, &[20, 20, 20, 20], &[2, 2, 2, 2]),
+        (3, &[20, 20, 20, 20, 20], &[10, 10, 10, 10, 10]),
+    ];
+
+    #[test]
+    fn covers_each_token_exactly_once_per_cycle() {
+        for &(n, caps, batches) in CASES {
+            let ts = tokens(n);
+            let plan = CyclePlan::build(&ts, &[], caps, batches);
+            let seen = counts(&plan);
+            assert_eq!(seen.len(), n, "{:?}", (n, caps, batches));
+            assert!(seen.values().all(|&c| c == 1), "{:?}", (n, caps, batches));
+            for t in &ts {
+                assert!(plan.refresh_interval(t).is_some(), "{} not owned", t);
+            }
+        }
+    }
+
+    #[test]
+    fn splits_tokens_by_capacity() {
+        let plan = CyclePlan::build(&tokens(100), &[], &[10, 30, 60], &[5, 5, 5]);
+        let lens: Vec<usize> = plan.nodes.iter().map(|n| n.batches.iter().map(Vec::len).sum()).collect();
+        assert_eq!(lens, [10, 30, 60]);
+        // 切分保持原顺序：每个节点分到连续区间
+        let first: Vec<&str> = plan.nodes.iter().map(|n| n.batches[0][0].as_str()).collect();
+        assert_eq!(first, ["t0", "t10", "t40"]);
+        assert_eq!(plan.nodes.iter().map(|n| n.rps).collect::<Vec<_>>(), [10.0, 30.0, 60.0]);
+    }
+
+    #[test]
+    fn batch_sizes_stay_within_one() {
+        for &(n, caps, batches) in CASES {
+            let plan = CyclePlan::build(&tokens(n), &[], caps, batches);
+            for (node, b) in plan.nodes.iter().zip(batches) {
+                let sizes: Vec<usize> = node.batches.iter().map(Vec::len).collect();
+                let (min, max) = (sizes.iter().min().copied().unwrap_or(0), sizes.iter().max().copied().unwrap_or(0));
+                assert!(max - min <= 1, "{:?}: {:?}", (n, caps, batches), sizes);
+                assert!(max <= *b, "{:?}: {:?}", (n, caps, batches), sizes);
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_tokens_repeat_without_sharing_a_batch() {
+        let ts = tokens(20);
+        let mut weights = vec![1; 20];
+        weights[3] = 4;
+        weights[11] = 2;
+        let plan = CyclePlan::build(&ts, &weights, &[20], &[4]);
+        let seen = counts(&plan);
+        assert_eq!((seen["t3"], seen["t11"], seen["t0"]), (4, 2, 1));
+        for b in &plan.nodes[0].batches {
+            let uniq: std::collections::HashSet<&String> = b.iter().collect();
+            assert_eq!(uniq.len(), b.len(), "duplicate in batch {:?}", b);
+        }
+        let cycle = plan.nodes[0].cycle();
+        assert_eq!(plan.refresh_interval("t3"), Some(cycle / 4));
+        assert_eq!(plan.max_refresh_interval(), cycle);
+    }
+
+    #[test]
+    fn slots_rotate_by_capacity() {
+        let plan = CyclePlan::build(&tokens(60), &[], &[20, 40], &[2, 2]);
+        assert_eq!(plan.order.iter().filter(|&&i| i == 1).count(), 2 * plan.order.iter().filter(|&&i| i == 0).count());
+        assert_eq!(plan.slot_interval, Duration::from_secs_f64(1.0 / 60.0));
+        assert_eq!(plan.token_rate, 120.0);
+        // 一个完整周期内每批恰好派发一次
+        let mut cur = plan.cursor();
+        // 两个节点的周期等长（10 批 / 20 rps = 20 批 / 40 rps），一个周期恰为全部批数个时间片
+        assert_eq!(plan.nodes[0].cycle(), plan.nodes[1].cycle());
+        let slots: usize = plan.nodes.iter().map(|n| n.batches.len()).sum();
+        let mut dispatched: Vec<usize> = vec![0; 60];
+        for _ in 0..slots {
+            if let (_, Some(b)) = plan.next_slot(&mut cur) {
+                for t in b {
+                    dispatched[t[1..].parse::<usize>().unwrap()] += 1;
+                }
+            }
+        }
+        assert!(dispatched.iter().all(|&c| c == 1), "{:?}", dispatched);
+    }
+
+    #[test]
+    fn rolling_plan_keeps_tick_across_rebuild() {
+        let start = Instant::now();
+        let cycle = CyclePlan::build(&tokens(10), &[], &[10], &[1]);
+        let mut plan = RollingPlan::new(cycle.clone(), vec!["a".into()], Duration::from_secs(1), start);
+        let first = plan.pop().unwrap();
+        assert_eq!((first.slot, first.at, first.tokens.as_slice()), (0, start, &["t0".to_string()][..]));
+        let next_at = plan.peek().unwrap().at;
+        plan.rebuild(cycle, vec!["b".into()], start);
+        // 节拍不变，时间片编号继续递增
+        let d = plan.peek().unwrap();
+        assert_eq!((d.at, d.node.as_str(), d.tokens.as_slice()), (next_at, "b", &["t0".to_string()][..]));
+        assert!(d.slot > first.slot);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::lease::Role;
+use poly_ob_common::redisx::RedisClient;
+use poly_ob_common::settings::QuarantineConfig;
+use poly_ob_common::types::QuarantinedToken;
+use std::collections::HashSet;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+/// 周期同步 Redis 隔离表，当前隔离集合经 watch 交给调度器从计划中剔除。
+/// 隔离满 `revalidate_secs` 的 token 由 leader 解除并重新纳入计划：仍然 4xx 时 Fetch 会再次对分隔离
+pub async fn quarantine_loop(
+    mut redis: RedisClient,
+    cfg: QuarantineConfig,
+    role_rx: watch::Receiver<Role>,
+    tx: watch::Sender<HashSet<String>>,
+) {
+    let mut tick = tokio::time::interval(Duration::from_millis(cfg.sync_ms.max(100)));
+    let revalidate_ms = cfg.revalidate_secs.saturating_mul(1000) as i64;
+    info!(revalidate_secs = cfg.revalidate_secs, "token quarantine enabled");
+    loop {
+        tick.tick().await;
+        let records = match redis.quarantined().await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("quarantine sync failed: {}", e);
+                continue;
+            }
+        };
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let (due, mut held): (Vec<QuarantinedToken>, Vec<QuarantinedToken>) =
+            records.into_iter().partition(|r| now_ms - r.at_ms >= revalidate_ms);
+        if !due.is_empty() && role_rx.borrow().is_leader() {
+            let ids: Vec<String> = due.iter().map(|r| r.token_id.clone()).collect();
+            match redis.release_quarantine(&ids).await {
+                Ok(()) => {
+                    for r in &due {
+                        info!(token = %r.token_id, since_ms = r.at_ms, "quarantine released for revalidation");
+                    }
+                }
+                Err(e) => {
+                    warn!("quarantine release failed: {}", e);
+                    held.extend(due);
+                }
+            }
+        } else {
+            held.extend(due);
+        }
+        let set: HashSet<String> = held.into_iter().map(|r| r.token_id).collect();
+        tx.send_if_modified(|cur| {
+            if *cur == set {
+                return false;
+            }
+            *cur = set;
+            true
+        });
+    }
+}
@@ -0,0 +1,282 @@
+use crate::conn::ConnPool;
+use crate::discovery::{apply_nodes, probe_nodes};
+use crate::plan::{NodeSpec, Topology};
+use crate::tiers::TierSet;
+use poly_ob_common::redisx::RedisClient;
+use poly_ob_common::settings::{parse_client, ClientConfig, ReloadConfig};
+use std::collections::HashSet;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+/// 按来源顺序合并去重：配置文件 tokens（保持文件顺序）、Redis 集合、市场发现
+pub fn merge_tokens(sources: &[&[String]]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    sources.iter().flat_map(|s| s.iter()).filter(|t| seen.insert(t.as_str())).cloned().collect()
+}
+
+/// 替换拓扑中的 token 列表并打印增减，返回是否有变化
+fn apply_tokens(topo: &mut Topology, tokens: Vec<String>) -> bool {
+    if topo.tokens == tokens {
+        return false;
+    }
+    {
+        let old: HashSet<&String> = topo.tokens.iter().collect();
+        let new: HashSet<&String> = tokens.iter().collect();
+        let added: Vec<&String> = tokens.iter().filter(|t| !old.contains(t)).collect();
+        let removed: Vec<&String> = topo.tokens.iter().filter(|t| !new.contains(t)).collect();
+        info!(added = added.len(), removed = removed.len(), total = tokens.len(), "token list reloaded");
+        for t in added {
+            info!(token = %t, "token added");
+        }
+        for t in removed {
+            info!(token = %t, "token removed");
+        }
+    }
+    topo.tokens = tokens;
+    true
+}
+
+/// 热加载：定期检查配置文件与 Redis token 集合，并接收市场发现结果；tokens / 静态节点变化时更新拓扑，
+/// 刷新档位变化时更新档位集合，调度器据此从下一个未派发时刻起重建计划，节拍不中断。其余配置项仍需重启生效
+pub struct Reloader {
+    path: String,
+    cfg: ReloadConfig,
+    redis: RedisClient,
+    pool: ConnPool,
+    /// 上一次读到的配置文件内容；内容不变时不重新解析
+    last_text: Option<String>,
+    cfg_tokens: Vec<String>,
+    set_tokens: Vec<String>,
+    market_tokens: Vec<String>,
+    market_rx: Option<watch::Receiver<Vec<String>>>,
+    static_nodes: Vec<NodeSpec>,
+    /// 启用节点发现时静态节点交给发现循环合并；否则直接写入拓扑
+    static_tx: Option<watch::Sender<Vec<NodeSpec>>>,
+    topo_tx: watch::Sender<Topology>,
+    tiers_tx: Option<watch::Sender<TierSet>>,
+}
+
+impl Reloader {
+    pub fn new(
+        path: &str,
+        cfg: &ClientConfig,
+        redis: RedisClient,
+        pool: ConnPool,
+        static_nodes: Vec<NodeSpec>,
+        static_tx: Option<watch::Sender<Vec<NodeSpec>>>,
+        topo_tx: watch::Sender<Topology>,
+    ) -> Self {
+        Self {
+            path: path.to_string(),
+            cfg: cfg.reload.clone(),
+            redis,
+            pool,
+            last_text: None,
+            cfg_tokens: cfg.tracked_tokens(),
+            set_tokens: Vec::new(),
+            market_tokens: Vec::new(),
+            market_rx: None,
+            static_nodes,
+            static_tx,
+            topo_tx,
+            tiers_tx: None,
+        }
+    }
+
+    /// 合并市场发现得到的 tokens
+    pub fn with_markets(mut self, market_rx: watch::Receiver<Vec<String>>) -> Self {
+        self.market_tokens = market_rx.borrow().clone();
+        self.market_rx = Some(market_rx);
+        self
+    }
+
+    /// 配置文件中的刷新档位（`tiers`、`default_refresh_ms`）随文件重新加载
+    pub fn with_tiers(mut self, tiers_tx: watch::Sender<TierSet>) -> Self {
+        self.tiers_tx = Some(tiers_tx);
+        self
+    }
+
+    pub async fn run(mut self) {
+        info!(
+            path = %self.path, watch_config = self.cfg.watch_config, token_set = ?self.cfg.token_set,
+            interval_ms = self.cfg.interval_ms, "hot reload enabled"
+        );
+        let mut tick = tokio::time::interval(Duration::from_millis(self.cfg.interval_ms.max(100)));
+        loop {
+            let market_changed = async {
+                match self.market_rx.as_mut() {
+                    Some(rx) => rx.changed().await.is_ok(),
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = tick.tick() => {
+                    if self.cfg.watch_config {
+                        self.check_config().await;
+                    }
+                    if let Some(key) = &self.cfg.token_set {
+                        match self.redis.token_set(key).await {
+                            Ok(v) => self.set_tokens = v,
+                            // 读取失败时沿用上一次的集合，避免误删全部 token
+                            Err(e) => warn!(%key, "token set read failed: {}", e),
+                        }
+                    }
+                }
+                ok = market_changed => match ok {
+                    true => self.market_tokens = self.market_rx.as_mut().map(|rx| rx.borrow_and_update().clone()).unwrap_or_default(),
+                    false => self.market_rx = None,
+                },
+            }
+            let tokens = merge_tokens(&[&self.cfg_tokens, &self.set_tokens, &self.market_tokens]);
+            self.topo_tx.send_if_modified(|topo| apply_tokens(topo, tokens));
+        }
+    }
+
+    async fn check_config(&mut self) {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!(path = %self.path, "config read failed: {}", e);
+                return;
+            }
+        };
+        if self.last_text.as_ref() == Some(&text) {
+            return;
+        }
+        let first = self.last_text.is_none();
+        self.last_text = Some(text);
+        let cfg = match parse_client(self.last_text.as_deref().unwrap_or_default()) {
+            Ok(c) => c,
+            Err(e) => {
+                // 编辑过程中的半成品文件：保持现有配置，等下一次修改
+                warn!(path = %self.path, "config parse failed, keeping current settings: {}", e);
+                return;
+            }
+        };
+        if !first {
+            info!(path = %self.path, "config file changed");
+        }
+        self.cfg_tokens = cfg.tracked_tokens();
+        // 先于 tokens 更新档位，新增的档位 token 一进入计划即按所属档位调度
+        if let Some(tx) = &self.tiers_tx {
+            let tiers = TierSet::from_config(&cfg);
+            tx.send_if_modified(|cur| {
+                if *cur == tiers {
+                    return false;
+                }
+                info!(tiers = cfg.tiers.len(), "refresh tiers reloaded");
+                *cur = tiers;
+                true
+            });
+        }
+
+        let addrs: Vec<&String> = self.static_nodes.iter().map(|n| &n.addr).collect();
+        if addrs.len() == cfg.fetch_nodes.len() && addrs.iter().zip(&cfg.fetch_nodes).all(|(a, b)| *a == b) {
+            return;
+        }
+        // 已知节点沿用原容量，仅对新地址发 hello
+        let fresh: Vec<String> = cfg.fetch_nodes.iter().filter(|a| !addrs.contains(a)).cloned().collect();
+        let probed = probe_nodes(&self.pool, &fresh).await;
+        let nodes: Vec<NodeSpec> = cfg
+            .fetch_nodes
+            .iter()
+            .filter_map(|a| self.static_nodes.iter().chain(&probed).find(|n| &n.addr == a).cloned())
+            .collect();
+        self.static_nodes = nodes.clone();
+        match &self.static_tx {
+            Some(tx) => {
+                tx.send_replace(nodes);
+            }
+            None => {
+                self.topo_tx.send_if_modified(|topo| apply_nodes(topo, nodes));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::MockRedis;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn config(redis_url: &str, tokens: &[&str], nodes: &[&str]) -> String {
+        format!(
+            r#"
+            redis_url = "{}"
+            base_url = "http://127.0.0.1:1"
+            tokens = {:?}
+            fetch_nodes = {:?}
+            [reload]
+            token_set = "extra"
+            interval_ms = 100
+            "#,
+            redis_url, tokens, nodes
+        )
+    }
+
+    async fn changed(rx: &mut watch::Receiver<Topology>) -> Topology {
+        tokio::time::timeout(Duration::from_secs(2), rx.changed()).await.expect("topology unchanged").unwrap();
+        rx.borrow_and_update().clone()
+    }
+
+    #[test]
+    fn merge_keeps_first_occurrence_order() {
+        let merged = merge_tokens(&[&strings(&["b", "a", "b"]), &strings(&["c", "a"]), &[], &strings(&["d", "c"])]);
+        assert_eq!(merged, ["b", "a", "c", "d"]);
+    }
+
+    #[test]
+    fn apply_tokens_reports_only_real_changes() {
+        let mut topo = Topology { tokens: strings(&["a", "b"]), nodes: vec![] };
+        assert!(!apply_tokens(&mut topo, strings(&["a", "b"])));
+        assert!(apply_tokens(&mut topo, strings(&["b", "a"])), "顺序变化同样影响切分");
+        assert!(apply_tokens(&mut topo, strings(&["b"])));
+        assert_eq!(topo.tokens, ["b"]);
+    }
+
+    #[tokio::test]
+    async fn reloads_tokens_and_nodes_from_file_set_and_markets() {
+        let redis = MockRedis::start().await;
+        redis.set_members("extra", &["c", "a"]);
+        let path = std::env::temp_dir().join(format!("poly-ob-reload-{}.toml", std::process::id()));
+        std::fs::write(&path, config(&redis.url, &["a", "b"], &["n1:1"])).unwrap();
+        let cfg = parse_client(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let static_nodes = vec![NodeSpec { addr: "n1:1".into(), capacity_rps: 50 }];
+        let topo = Topology { tokens: cfg.tracked_tokens(), nodes: static_nodes.clone() };
+        let (topo_tx, mut topo_rx) = watch::channel(topo);
+        let (market_tx, market_rx) = watch::channel(Vec::new());
+        let client = RedisClient::connect(&redis.url).await.unwrap();
+        let reloader = Reloader::new(path.to_str().unwrap(), &cfg, client, ConnPool::default(), static_nodes, None, topo_tx)
+            .with_markets(market_rx);
+        let task = tokio::spawn(reloader.run());
+
+        assert_eq!(changed(&mut topo_rx).await.tokens, ["a", "b", "c"]);
+        market_tx.send_replace(strings(&["d", "b"]));
+        assert_eq!(changed(&mut topo_rx).await.tokens, ["a", "b", "c", "d"]);
+
+        // 新地址经 hello 探测（连接被拒，按默认容量），已知节点沿用原容量
+        std::fs::write(&path, config(&redis.url, &["b", "e"], &["n1:1", "127.0.0.1:1"])).unwrap();
+        let mut topo = changed(&mut topo_rx).await;
+        while topo.nodes.len() < 2 || topo.tokens[0] != "b" {
+            topo = changed(&mut topo_rx).await;
+        }
+        assert_eq!(topo.tokens, ["b", "e", "a", "c", "d"]);
+        let nodes: Vec<_> = topo.nodes.iter().map(|n| (n.addr.as_str(), n.capacity_rps)).collect();
+        assert_eq!(nodes, [("n1:1", 50), ("127.0.0.1:1", 20)]);
+
+        // 解析失败的文件与读取失败的集合都保持现状
+        std::fs::write(&path, "tokens = [").unwrap();
+        redis.set_down(true);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!topo_rx.has_changed().unwrap());
+        assert_eq!(topo_rx.borrow().tokens, ["b", "e", "a", "c", "d"]);
+
+        task.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,448 @@
+use crate::activity::Activity;
+use crate::batch::{classify, BatchController};
+use crate::conn::ConnPool;
+use crate::health::{ack_healthy, HealthTracker};
+use crate::lease::Role;
+use crate::plan::{default_batch, CyclePlan, Dispatch, RollingPlan, Topology};
+use crate::tiers::{weights, TierSet};
+use anyhow::Result;
+use poly_ob_common::protocol::{Ack, AckStatus, Command};
+use poly_ob_common::settings::{BatchConfig, ClientConfig};
+use poly_ob_common::types::QuarantinedToken;
+use std::collections::{HashMap, HashSet, VecDeque};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep_until, Duration, Instant, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+/// 等 Ack 时在指令时限之外多等的时长，覆盖传输与写 Redis 的耗时
+const ACK_GRACE: Duration = Duration::from_millis(500);
+/// 批大小调整后检查是否可以重建计划的间隔
+const REPLAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 按拓扑、刷新档位、变化率与各节点当前批大小构建周期计划；新节点以初始批大小加入，下线节点的控制器被移除
+pub fn build_cycle(
+    topo: &Topology,
+    tiers: &TierSet,
+    activity: Option<&Activity>,
+    cfg: &BatchConfig,
+    ctl: &mut HashMap<String, BatchController>,
+) -> CyclePlan {
+    let caps = topo.caps();
+    let initial = cfg.initial.unwrap_or_else(|| default_batch(topo.tokens.len(), caps.iter().sum()));
+    ctl.retain(|addr, _| topo.nodes.iter().any(|n| &n.addr == addr));
+    let batches: Vec<usize> = topo
+        .nodes
+        .iter()
+        .map(|n| ctl.entry(n.addr.clone()).or_insert_with(|| BatchController::new(initial, cfg)).size())
+        .collect();
+    let budget: f64 = caps.iter().zip(&batches).map(|(&c, &b)| c as f64 * b as f64).sum();
+    let mut rates = tiers.rates(&topo.tokens, budget, activity.map(Activity::floor));
+    if let Some(a) = activity {
+        a.bias(&topo.tokens, &mut rates, budget);
+    }
+    CyclePlan::build(&topo.tokens, &weights(&rates), &caps, &batches)
+}
+
+/// 重建后跑完一个完整周期所需的时长；多留一个时间片，因为重建从下一个未派发时刻起生效
+fn cycle_len(plan: &CyclePlan) -> Duration {
+    plan.max_refresh_interval() + plan.slot_interval
+}
+
+fn log_plan(plan: &CyclePlan, topo: &Topology, tiers: &TierSet) {
+    info!(
+        tokens = topo.tokens.len(),
+        nodes = topo.nodes.len(),
+        batches = ?plan.nodes.iter().map(|n| n.batches.first().map_or(0, Vec::len)).collect::<Vec<_>>(),
+        slot_ms = plan.slot_interval.as_secs_f64() * 1000.0,
+        refresh_ms = plan.max_refresh_interval().as_millis() as u64,
+        "plan built"
+    );
+    for t in &topo.tokens {
+        let refresh_ms = plan.refresh_interval(t).map(|d| d.as_millis() as u64);
+        debug!(token = %t, ?refresh_ms, "expected refresh interval");
+    }
+    tiers.log_report(plan, &topo.tokens);
+}
+
+/// 一个时间片的执行结果，回传调度器用于自适应与健康判定
+struct Outcome {
+    node: String,
+    slot: u64,
+    size: usize,
+    /// 本片的 tokens，仅在开启变化驱动调度时携带
+    tokens: Vec<String>,
+    result: Result<Ack, String>,
+}
+
+/// 各节点会话中已确认的 payload：只有 `set` 收到 Ok Ack 后才记为节点的最近 payload，
+/// 与本片相同时发 `replay`。发出 `set` 即作废已确认的记录（节点状态待定），
+/// 在途期间的派发一律发完整 `set`；只认最后一次发出的 `set` 的 Ack，避免乱序 Ack 覆盖
+#[derive(Debug, Default)]
+struct Payloads {
+    confirmed: HashMap<String, Vec<String>>,
+    /// 各节点最后一次发出 `set` 的时间片及其 payload
+    pending: HashMap<String, (u64, Vec<String>)>,
+}
+
+impl Payloads {
+    fn command(&mut self, node: &str, slot: u64, tokens: Vec<String>) -> Command {
+        if self.confirmed.get(node) == Some(&tokens) {
+            return Command::Replay;
+        }
+        self.confirmed.remove(node);
+        self.pending.insert(node.to_string(), (slot, tokens.clone()));
+        Command::Set { tokens }
+    }
+
+    fn ack(&mut self, node: &str, slot: u64, ok: bool) {
+        let latest = self.pending.get(node).is_some_and(|(s, _)| *s == slot);
+        if !ok {
+            // 节点未执行（拒绝或出错），其会话状态未知，下次须重发完整 payload
+            self.confirmed.remove(node);
+            if latest {
+                self.pending.remove(node);
+            }
+        } else if latest {
+            if let Some((_, tokens)) = self.pending.remove(node) {
+                self.confirmed.insert(node.to_string(), tokens);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.confirmed.clear();
+        self.pending.clear();
+    }
+
+    fn retain(&mut self, addrs: &[String]) {
+        self.confirmed.retain(|addr, _| addrs.contains(addr));
+        self.pending.retain(|addr, _| addrs.contains(addr));
+    }
+}
+
+pub struct Scheduler {
+    cfg: ClientConfig,
+    pool: ConnPool,
+    /// 全部已知节点；实际参与轮转的是其中健康的子集
+    topo: Topology,
+    tiers: TierSet,
+    /// 开启变化驱动调度时的各 token 变化率
+    activity: Option<Activity>,
+    health: HealthTracker,
+    batches: HashMap<String, BatchController>,
+    plan: RollingPlan,
+    /// 陈旧巡检提交的 token，插队到接下来的派发中
+    urgent: VecDeque<String>,
+    urgent_set: HashSet<String>,
+    /// 被隔离的坏 token，不进入计划与插队
+    quarantined: HashSet<String>,
+    last_rebuild: Instant,
+    /// 当前计划中最长的刷新间隔；按变化率或批大小重建不早于一个完整周期（重建后各节点从首批开始），
+    /// 避免周期尾部的 token 被饿死
+    cycle_len: Duration,
+    /// 每个节点已确认的最近 payload；与本片相同时只发 replay
+    payloads: Payloads,
+    outcome_tx: mpsc::UnboundedSender<Outcome>,
+    outcome_rx: mpsc::UnboundedReceiver<Outcome>,
+    /// 仅 leader 派发；备机照常维护计划、健康与拓扑，以便接管后立即生效
+    role: Role,
+}
+
+impl Scheduler {
+    pub fn new(cfg: ClientConfig, pool: ConnPool, topo: Topology) -> Self {
+        let horizon = Duration::from_secs(cfg.plan_horizon_secs.max(1));
+        let health = HealthTracker::new(cfg.health.clone());
+        let tiers = TierSet::from_config(&cfg);
+        let activity = cfg.change_bias.enabled.then(|| Activity::new(&cfg.change_bias));
+        let mut batches = HashMap::new();
+        let cycle = build_cycle(&topo, &tiers, activity.as_ref(), &cfg.batch, &mut batches);
+        log_plan(&cycle, &topo, &tiers);
+        let cycle_len = cycle_len(&cycle);
+        let plan = RollingPlan::new(cycle, topo.addrs(), horizon, Instant::now() + Duration::from_millis(200));
+        let (outcome_tx, outcome_rx) = mpsc::unbounded_channel();
+        Self {
+            cfg,
+            pool,
+            topo,
+            tiers,
+            activity,
+            health,
+            batches,
+            plan,
+            urgent: VecDeque::new(),
+            urgent_set: HashSet::new(),
+            quarantined: HashSet::new(),
+            last_rebuild: Instant::now(),
+            cycle_len,
+            payloads: Payloads::default(),
+            outcome_tx,
+            outcome_rx,
+            role: Role::Standby,
+        }
+    }
+
+    pub async fn run(
+        mut self,
+        mut topo_rx: watch::Receiver<Topology>,
+        mut probe_rx: mpsc::UnboundedReceiver<(String, bool)>,
+        mut role_rx: watch::Receiver<Role>,
+        mut urgent_rx: mpsc::UnboundedReceiver<Vec<String>>,
+        mut quarantine_rx: watch::Receiver<HashSet<String>>,
+        mut tiers_rx: watch::Receiver<TierSet>,
+    ) -> Result<()> {
+        self.role = *role_rx.borrow_and_update();
+        self.quarantined = quarantine_rx.borrow_and_update().clone();
+        let tiers = tiers_rx.borrow_and_update().clone();
+        if !self.quarantined.is_empty() || tiers != self.tiers {
+            self.tiers = tiers;
+            self.replan();
+        }
+        // 批大小变化后不立即重建，待当前周期跑完再合并生效
+        let mut replan = tokio::time::interval(REPLAN_INTERVAL);
+        replan.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut dirty = false;
+        let mut role_open = true;
+        let mut quarantine_open = true;
+        let mut tiers_open = true;
+        let mut bias = tokio::time::interval(Duration::from_secs(self.cfg.change_bias.replan_secs.max(1)));
+        bias.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            self.plan.fill(Instant::now());
+            // 计划为空（无 token 或无可用节点）时只等待拓扑/健康变化
+            let at = self.plan.peek().map(|d| d.at);
+            tokio::select! {
+                _ = sleep_until(at.unwrap_or_else(Instant::now)), if at.is_some() => {
+                    if let Some(d) = self.plan.pop() {
+                        self.dispatch(d);
+                    }
+                }
+                Some(o) = self.outcome_rx.recv() => {
+                    log_outcome(&o);
+                    if let (Some(a), Ok(ack)) = (self.activity.as_mut(), &o.result) {
+                        if ack.status == AckStatus::Ok && ack.fetched > 0 {
+                            let changed: HashSet<&str> = ack.changed.iter().map(String::as_str).collect();
+                            for t in &o.tokens {
+                                a.observe(t, changed.contains(t.as_str()));
+                            }
+                        }
+                    }
+                    if let Ok(ack) = &o.result {
+                        if self.cfg.quarantine.enabled && self.quarantine(&ack.quarantined) {
+                            self.replan();
+                        }
+                    }
+                    self.payloads.ack(&o.node, o.slot, matches!(&o.result, Ok(ack) if ack.status == AckStatus::Ok));
+                    if let Some(c) = self.batches.get_mut(&o.node) {
+                        let before = c.size();
+                        if c.observe(classify(&o.result, self.cfg.batch.target_latency_ms)) {
+                            info!(node = %o.node, from = before, to = c.size(), "batch size adjusted");
+                            dirty = true;
+                        }
+                    }
+                    // 故障转移不等合并间隔，立即重建
+                    if self.health.record_ack(&o.node, ack_healthy(&o.result)) {
+                        self.replan();
+                    }
+                }
+                Some(tokens) = urgent_rx.recv() => {
+                    for t in tokens {
+                        if self.urgent_set.insert(t.clone()) {
+                            self.urgent.push_back(t);
+                        }
+                    }
+                }
+                Some((addr, ok)) = probe_rx.recv() => {
+                    if self.health.record_probe(&addr, ok) {
+                        self.replan();
+                    }
+                }
+                _ = replan.tick(), if dirty => {
+                    if self.last_rebuild.elapsed() >= self.cycle_len {
+                        dirty = false;
+                        self.replan();
+                    }
+                }
+                _ = bias.tick(), if self.activity.is_some() => {
+                    if self.last_rebuild.elapsed() >= self.cycle_len {
+                        self.replan();
+                    }
+                }
+                changed = role_rx.changed(), if role_open => {
+                    if changed.is_err() {
+                        // 未启用选主时角色固定，发送端不存在
+                        role_open = false;
+                        continue;
+                    }
+                    self.role = *role_rx.borrow_and_update();
+                    // 换届后节点会话可能已被其他 leader 改写
+                    self.payloads.clear();
+                    info!(role = ?self.role, "scheduler role changed");
+                }
+                changed = quarantine_rx.changed(), if quarantine_open => {
+                    if changed.is_err() {
+                        // 未启用隔离时发送端不存在
+                        quarantine_open = false;
+                        continue;
+                    }
+                    let set = quarantine_rx.borrow_and_update().clone();
+                    if set != self.quarantined {
+                        info!(quarantined = set.len(), "quarantine synced");
+                        self.quarantined = set;
+                        self.replan();
+                    }
+                }
+                changed = tiers_rx.changed(), if tiers_open => {
+                    if changed.is_err() {
+                        // 未启用热加载时档位固定，发送端不存在
+                        tiers_open = false;
+                        continue;
+                    }
+                    self.tiers = tiers_rx.borrow_and_update().clone();
+                    self.replan();
+                }
+                changed = topo_rx.changed() => {
+                    if changed.is_err() {
+                        anyhow::bail!("topology channel closed");
+                    }
+                    self.topo = topo_rx.borrow_and_update().clone();
+                    self.health.retain(&self.topo);
+                    if let Some(a) = self.activity.as_mut() {
+                        a.retain(&self.topo.tokens);
+                    }
+                    let addrs = self.topo.addrs();
+                    self.pool.retain(&addrs);
+                    self.payloads.retain(&addrs);
+                    self.replan();
+                }
+            }
+        }
+    }
+
+    /// 记录 Ack 带回的隔离 token，返回是否有新增
+    fn quarantine(&mut self, tokens: &[QuarantinedToken]) -> bool {
+        let mut added = false;
+        for t in tokens {
+            if self.quarantined.insert(t.token_id.clone()) {
+                warn!(token = %t.token_id, node = %t.node_id, http_status = ?t.http_status, err = %t.error, "token quarantined");
+                added = true;
+            }
+        }
+        added
+    }
+
+    /// 以健康节点重建周期计划，从下一个未派发时刻起生效；隔离中的 token 不参与
+    fn replan(&mut self) {
+        let mut active = self.health.filter(&self.topo);
+        active.tokens.retain(|t| !self.quarantined.contains(t));
+        let cycle = build_cycle(&active, &self.tiers, self.activity.as_ref(), &self.cfg.batch, &mut self.batches);
+        log_plan(&cycle, &active, &self.tiers);
+        if let Some(a) = &self.activity {
+            info!(active = a.active(0.5), tokens = active.tokens.len(), "plan biased by change rate");
+        }
+        self.last_rebuild = Instant::now();
+        self.cycle_len = cycle_len(&cycle);
+        self.plan.rebuild(cycle, active.addrs(), Instant::now());
+    }
+
+    /// 发送一个时间片的指令（长连接，长度前缀 JSON），不阻塞节拍；Ack 在独立任务中关联到该时间片
+    fn dispatch(&mut self, mut d: Dispatch) {
+        if !self.role.is_leader() {
+            return;
+        }
+        let fence = self.role.fence();
+        // 陈旧 token 追加到本片（不超过 `boost` 个），抓取失败的由下一轮巡检重新提交
+        let limit = d.tokens.len() + self.cfg.watchdog.boost;
+        while d.tokens.len() < limit {
+            let Some(t) = self.urgent.pop_front() else { break };
+            self.urgent_set.remove(&t);
+            if !d.tokens.contains(&t) && !self.quarantined.contains(&t) {
+                d.tokens.push(t);
+            }
+        }
+        // 每个节点一条长连接，指令按 id 关联 Ack
+        let conn = self.pool.get(&d.node);
+        let size = d.tokens.len();
+        let tokens = if self.activity.is_some() { d.tokens.clone() } else { Vec::new() };
+        let cmd = self.payloads.command(&d.node, d.slot, d.tokens);
+        let (node, slot) = (d.node, d.slot);
+        let deadline = Duration::from_millis(self.cfg.slice_deadline_ms);
+        let outcome_tx = self.outcome_tx.clone();
+        tokio::spawn(async move {
+            let result = conn.call_with_deadline(cmd, fence, deadline, ACK_GRACE).await.map_err(|e| e.to_string());
+            let _ = outcome_tx.send(Outcome { node, slot, size, tokens, result });
+        });
+    }
+}
+
+fn log_outcome(o: &Outcome) {
+    let (slot, node, size) = (o.slot, o.node.as_str(), o.size);
+    match &o.result {
+        Ok(ack) if ack.status == AckStatus::Ok => info!(
+            slot, node, size, fetched = ack.fetched, updated = ack.updated,
+            latency_ms = ack.latency_ms, "slice acked"
+        ),
+        Ok(ack) if ack.status == AckStatus::RateLimited => {
+            warn!(slot, node, size, "slice rejected by node rate limit")
+        }
+        Ok(ack) if ack.status == AckStatus::StaleFence => {
+            error!(slot, node, err = ack.error.as_deref().unwrap_or(""), "slice rejected: fenced out by a newer leader")
+        }
+        Ok(ack) => warn!(
+            slot, node, size, status = ?ack.status, http_status = ?ack.http_status,
+            err = ack.error.as_deref().unwrap_or(""), "slice failed"
+        ),
+        Err(e) => error!(slot, node, size, "send failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(ts: &[&str]) -> Vec<String> {
+        ts.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn is_replay(c: &Command) -> bool {
+        matches!(c, Command::Replay)
+    }
+
+    #[test]
+    fn replay_only_after_set_is_acked() {
+        let mut p = Payloads::default();
+        assert!(!is_replay(&p.command("a", 1, tokens(&["x"]))));
+        // Ack 未回时同一 payload 仍发 set
+        assert!(!is_replay(&p.command("a", 2, tokens(&["x"]))));
+        p.ack("a", 1, true);
+        assert!(!is_replay(&p.command("a", 3, tokens(&["x"]))), "ack of an older set is ignored");
+        p.ack("a", 2, true);
+        p.ack("a", 3, true);
+        assert!(is_replay(&p.command("a", 4, tokens(&["x"]))));
+    }
+
+    #[test]
+    fn rejected_set_forces_a_full_set_next_time() {
+        let mut p = Payloads::default();
+        p.command("a", 1, tokens(&["x"]));
+        p.ack("a", 1, true);
+        p.command("a", 2, tokens(&["y"]));
+        p.ack("a", 2, false);
+        // 节点未保存 y，也不能假定仍是 x
+        assert!(!is_replay(&p.command("a", 3, tokens(&["y"]))));
+        assert!(!is_replay(&p.command("a", 4, tokens(&["x"]))));
+    }
+
+    #[test]
+    fn failed_replay_and_role_change_invalidate() {
+        let mut p = Payloads::default();
+        p.command("a", 1, tokens(&["x"]));
+        p.ack("a", 1, true);
+        assert!(is_replay(&p.command("a", 2, tokens(&["x"]))));
+        p.ack("a", 2, false);
+        assert!(!is_replay(&p.command("a", 3, tokens(&["x"]))));
+        p.ack("a", 3, true);
+        p.clear();
+        assert!(!is_replay(&p.command("a", 4, tokens(&["x"]))));
+    }
+}
@@ -0,0 +1,181 @@
+//! 测试用的最小 Redis（RESP）替身，监听回环地址的随机端口；按脚本 SHA 模拟租约脚本
+
+use poly_ob_common::lua::{LUA_LEASE_ACQUIRE, LUA_LEASE_RELEASE, LUA_LEASE_RENEW};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Default)]
+struct State {
+    /// 租约持有者（不模拟 TTL，过期由测试调用 [`MockRedis::expire`]）
+    holder: Option<String>,
+    fence: u64,
+    /// 为真时所有命令返回错误，模拟 Redis 故障
+    down: bool,
+    /// SMEMBERS 返回的集合
+    sets: HashMap<String, Vec<String>>,
+    /// HMGET 读取的哈希
+    hashes: HashMap<String, HashMap<String, String>>,
+    /// 收到的命令；租约脚本记为 `lease:acquire` 等
+    commands: Vec<Vec<String>>,
+}
+
+/// Redis 替身：租约脚本按持有者与 fencing 计数器模拟，SMEMBERS / HMGET 读取预置的集合与哈希，PING 回 PONG，
+/// 其余命令默认 `+OK`
+#[derive(Clone)]
+pub struct MockRedis {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+fn sha(script: &str) -> String {
+    format!("{:x}", Sha1::digest(script.as_bytes()))
+}
+
+impl MockRedis {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+        let scripts = Arc::new(HashMap::from([
+            (sha(LUA_LEASE_ACQUIRE), "lease:acquire"),
+            (sha(LUA_LEASE_RENEW), "lease:renew"),
+            (sha(LUA_LEASE_RELEASE), "lease:release"),
+        ]));
+        let shared = state.clone();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let (state, scripts) = (shared.clone(), scripts.clone());
+                tokio::spawn(async move {
+                    let (rd, mut wr) = sock.into_split();
+                    let mut rd = BufReader::new(rd);
+                    while let Some(mut args) = read_resp_command(&mut rd).await {
+                        let reply = {
+                            let mut st = state.lock().unwrap();
+                            let name = args[0].to_ascii_uppercase();
+                            if name == "EVALSHA" {
+                                if let Some(script) = scripts.get(&args[1]) {
+                                    args[0] = script.to_string();
+                                }
+                            }
+                            let reply = reply(&mut st, &name, &args);
+                            st.commands.push(args);
+                            reply
+                        };
+                        if wr.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        Self { url, state }
+    }
+
+    /// 租约被 `holder` 持有（模拟另一实例抢到租约，fencing token 随之递增）
+    pub fn hold(&self, holder: &str) {
+        let mut st = self.state.lock().unwrap();
+        st.holder = Some(holder.into());
+        st.fence += 1;
+    }
+
+    /// 租约过期
+    pub fn expire(&self) {
+        self.state.lock().unwrap().holder = None;
+    }
+
+    pub fn holder(&self) -> Option<String> {
+        self.state.lock().unwrap().holder.clone()
+    }
+
+    pub fn set_down(&self, down: bool) {
+        self.state.lock().unwrap().down = down;
+    }
+
+    pub fn set_members(&self, key: &str, members: &[&str]) {
+        let members = members.iter().map(|m| m.to_string()).collect();
+        self.state.lock().unwrap().sets.insert(key.into(), members);
+    }
+
+    pub fn set_field(&self, key: &str, field: &str, value: impl ToString) {
+        let mut st = self.state.lock().unwrap();
+        st.hashes.entry(key.into()).or_default().insert(field.into(), value.to_string());
+    }
+
+    /// 收到的某命令（如 `PUBLISH`、`lease:renew`）的全部参数
+    pub fn calls(&self, name: &str) -> Vec<Vec<String>> {
+        let st = self.state.lock().unwrap();
+        st.commands.iter().filter(|c| c[0].eq_ignore_ascii_case(name)).cloned().collect()
+    }
+}
+
+fn reply(st: &mut State, name: &str, args: &[String]) -> Vec<u8> {
+    if st.down {
+        return b"-ERR mock redis down\r\n".to_vec();
+    }
+    // EVALSHA sha numkeys key... holder [ttl]
+    let numkeys = args.get(2).and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+    let holder = args.get(3 + numkeys);
+    let int = |v: u64| format!(":{}\r\n", v).into_bytes();
+    match args[0].as_str() {
+        "lease:acquire" => match st.holder {
+            Some(_) => b"$-1\r\n".to_vec(),
+            None => {
+                st.holder = holder.cloned();
+                st.fence += 1;
+                int(st.fence)
+            }
+        },
+        "lease:renew" => int((st.holder.as_ref() == holder) as u64),
+        "lease:release" if st.holder.is_some() && st.holder.as_ref() == holder => {
+            st.holder = None;
+            int(1)
+        }
+        "lease:release" => int(0),
+        _ if name == "EVALSHA" => b"-NOSCRIPT No matching script\r\n".to_vec(),
+        _ if name == "PING" => b"+PONG\r\n".to_vec(),
+        _ if name == "SMEMBERS" => array(st.sets.get(&args[1]).map(Vec::as_slice).unwrap_or_default()),
+        _ if name == "HMGET" => {
+            let hash = st.hashes.get(&args[1]);
+            let mut out = format!("*{}\r\n", args.len() - 2).into_bytes();
+            for f in &args[2..] {
+                match hash.and_then(|h| h.get(f)) {
+                    Some(v) => out.extend(format!("${}\r\n{}\r\n", v.len(), v).into_bytes()),
+                    None => out.extend(b"$-1\r\n"),
+                }
+            }
+            out
+        }
+        _ => b"+OK\r\n".to_vec(),
+    }
+}
+
+fn array(items: &[String]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", items.len());
+    for i in items {
+        out.push_str(&format!("${}\r\n{}\r\n", i.len(), i));
+    }
+    out.into_bytes()
+}
+
+/// 读取一条 RESP 数组命令；连接关闭时为 `None`
+async fn read_resp_command<R: AsyncRead + Unpin>(rd: &mut BufReader<R>) -> Option<Vec<String>> {
+    let mut line = String::new();
+    if rd.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    let n: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(n);
+    for _ in 0..n {
+        line.clear();
+        rd.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut buf = vec![0; len + 2];
+        rd.read_exact(&mut buf).await.ok()?;
+        buf.truncate(len);
+        args.push(String::from_utf8_lossy(&buf).into_owned());
+    }
+    Some(args)
+}
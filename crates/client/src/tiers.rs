@@ -0,0 +1,208 @@
+use crate::plan::CyclePlan;
+use poly_ob_common::settings::ClientConfig;
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+/// 单个 token 相对最慢 token 的刷新倍数上限，避免极端配置把周期展开得过长
+const MAX_WEIGHT: u32 = 1000;
+
+/// 刷新档位集合；最后一个隐含档位 `default` 收容未归档的 token
+#[derive(Debug, Clone, PartialEq)]
+pub struct TierSet {
+    names: Vec<String>,
+    /// 各档位目标刷新间隔；`None` 为尽力而为
+    targets: Vec<Option<Duration>>,
+    tier_of: HashMap<String, usize>,
+}
+
+/// 档位的目标与计划实际可达的刷新间隔
+#[derive(Debug, Clone, Serialize)]
+pub struct TierReport {
+    pub name: String,
+    pub tokens: usize,
+    pub target_ms: Option<u64>,
+    /// 档内最慢 token 的期望刷新间隔
+    pub achieved_ms: u64,
+    pub satisfied: bool,
+}
+
+/// 把各 token 的刷新频率换算为一个周期内的刷新次数（最慢的 token 为 1）；
+/// 只看相对大小，富余预算的等比放大不影响结果。频率为 0（预算耗尽）的 token 仍保证每周期一次
+pub fn weights(rates: &[f64]) -> Vec<u32> {
+    let slowest = rates.iter().copied().filter(|&f| f > 0.0).fold(f64::INFINITY, f64::min);
+    if !slowest.is_finite() {
+        return vec![1; rates.len()];
+    }
+    rates.iter().map(|&f| ((f / slowest - 1e-9).ceil() as u32).clamp(1, MAX_WEIGHT)).collect()
+}
+
+impl TierSet {
+    pub fn from_config(cfg: &ClientConfig) -> Self {
+        let mut names: Vec<String> = cfg.tiers.iter().map(|t| t.name.clone()).collect();
+        let mut targets: Vec<Option<Duration>> =
+            cfg.tiers.iter().map(|t| Some(Duration::from_millis(t.refresh_ms.max(1)))).collect();
+        let mut tier_of = HashMap::new();
+        for (i, t) in cfg.tiers.iter().enumerate() {
+            for token in &t.tokens {
+                // 同一 token 出现在多个档位时取靠前（优先级高）的
+                tier_of.entry(token.clone()).or_insert(i);
+            }
+        }
+        names.push("default".into());
+        targets.push(cfg.default_refresh_ms.map(|ms| Duration::from_millis(ms.max(1))));
+        Self { names, targets, tier_of }
+    }
+
+    fn tier(&self, token: &str) -> usize {
+        self.tier_of.get(token).copied().unwrap_or(self.names.len() - 1)
+    }
+
+    /// 满足全部目标所需的 token 刷新吞吐（token/s）
+    pub fn required_rate(&self, tokens: &[String]) -> f64 {
+        tokens.iter().filter_map(|t| self.targets[self.tier(t)]).map(|d| 1.0 / d.as_secs_f64()).sum()
+    }
+
+    /// 把集群刷新预算（token/s）按优先级分给各档位，返回每个 token 的刷新频率（次/s）：
+    /// 有目标的档位依次取所需预算，尽力而为的 token 均分剩余预算；给定 `best_effort_floor` 时
+    /// 尽力而为的 token 只取该保底频率，剩余预算留给调用方另行分配
+    pub fn rates(&self, tokens: &[String], budget: f64, best_effort_floor: Option<f64>) -> Vec<f64> {
+        let idx: Vec<usize> = tokens.iter().map(|t| self.tier(t)).collect();
+        let mut counts = vec![0usize; self.names.len()];
+        for &i in &idx {
+            counts[i] += 1;
+        }
+        let mut rate = vec![0.0; self.names.len()];
+        let mut remaining = budget.max(0.0);
+        for (g, target) in self.targets.iter().enumerate() {
+            if let (Some(t), true) = (target, counts[g] > 0) {
+                rate[g] = (counts[g] as f64 / t.as_secs_f64()).min(remaining);
+                remaining -= rate[g];
+            }
+        }
+        let best_effort: usize = (0..counts.len()).filter(|&g| self.targets[g].is_none()).map(|g| counts[g]).sum();
+        if best_effort > 0 {
+            // 尽力而为的 token 不比任何有目标的档位刷新得更勤
+            let cap = (0..counts.len())
+                .filter(|&g| counts[g] > 0)
+                .filter_map(|g| self.targets[g])
+                .map(|t| 1.0 / t.as_secs_f64())
+                .fold(best_effort_floor.unwrap_or(f64::INFINITY), f64::min);
+            let each = (remaining / best_effort as f64).min(cap);
+            for g in (0..counts.len()).filter(|&g| self.targets[g].is_none()) {
+                rate[g] = each * counts[g] as f64;
+            }
+        }
+        idx.iter().map(|&g| rate[g] / counts[g] as f64).collect()
+    }
+
+    /// 对照计划检查各档位目标是否可达
+    pub fn report(&self, plan: &CyclePlan, tokens: &[String]) -> Vec<TierReport> {
+        let mut achieved = vec![Duration::ZERO; self.names.len()];
+        let mut counts = vec![0usize; self.names.len()];
+        for t in tokens {
+            let g = self.tier(t);
+            counts[g] += 1;
+            achieved[g] = achieved[g].max(plan.refresh_interval(t).unwrap_or(Duration::MAX));
+        }
+        (0..self.names.len())
+            .filter(|&g| counts[g] > 0)
+            .map(|g| TierReport {
+                name: self.names[g].clone(),
+                tokens: counts[g],
+                target_ms: self.targets[g].map(|d| d.as_millis() as u64),
+                achieved_ms: achieved[g].as_millis().min(u64::MAX as u128) as u64,
+                satisfied: match self.targets[g] {
+                    Some(t) => achieved[g] <= t,
+                    None => true,
+                },
+            })
+            .collect()
+    }
+
+    /// 打印各档位目标与可达情况；预算不足或目标不可达时告警
+    pub fn log_report(&self, plan: &CyclePlan, tokens: &[String]) {
+        let required = self.required_rate(tokens);
+        if required > plan.token_rate {
+            warn!(
+                required_tps = required, budget_tps = plan.token_rate,
+                "refresh targets exceed cluster budget, lower tiers degraded"
+            );
+        }
+        for r in self.report(plan, tokens) {
+            if r.satisfied {
+                info!(tier = %r.name, tokens = r.tokens, target_ms = ?r.target_ms, achieved_ms = r.achieved_ms, "tier refresh");
+            } else {
+                warn!(tier = %r.name, tokens = r.tokens, target_ms = ?r.target_ms, achieved_ms = r.achieved_ms, "tier target unsatisfiable");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poly_ob_common::settings::parse_client;
+
+    fn tier_set(hot_ms: u64) -> (TierSet, Vec<String>) {
+        let cfg = parse_client(&format!(
+            r#"
+            redis_url = "redis://127.0.0.1"
+            base_url = "http://127.0.0.1"
+            tokens = ["t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", "t8", "t9"]
+            [[tiers]]
+            name = "hot"
+            refresh_ms = {}
+            tokens = ["h0", "h1"]
+            "#,
+            hot_ms
+        ))
+        .unwrap();
+        (TierSet::from_config(&cfg), cfg.tracked_tokens())
+    }
+
+    #[test]
+    fn hot_tier_takes_its_share_first() {
+        let (tiers, tokens) = tier_set(250);
+        assert_eq!(tokens.len(), 12);
+        let rates = tiers.rates(&tokens, 20.0, None);
+        // 热档 2 个 token × 4 次/s，其余 12 token/s 由 10 个尽力而为的 token 均分
+        assert!((rates[10] - 4.0).abs() < 1e-9 && (rates[11] - 4.0).abs() < 1e-9);
+        assert!(rates[..10].iter().all(|&r| (r - 1.2).abs() < 1e-9));
+        assert!((tiers.required_rate(&tokens) - 8.0).abs() < 1e-9);
+        let w = weights(&rates);
+        assert_eq!((w[0], w[10]), (1, 4));
+
+        // 预算不足时热档先取，尽力而为的 token 分不到预算
+        let rates = tiers.rates(&tokens, 5.0, None);
+        assert!((rates[10] - 2.5).abs() < 1e-9 && rates[0] == 0.0);
+        assert_eq!(weights(&rates)[0], 1);
+    }
+
+    #[test]
+    fn best_effort_never_outpaces_a_target_tier() {
+        let (tiers, tokens) = tier_set(1000);
+        let rates = tiers.rates(&tokens, 1000.0, None);
+        assert!(rates.iter().all(|&r| (r - 1.0).abs() < 1e-9), "{:?}", rates);
+        let rates = tiers.rates(&tokens, 1000.0, Some(0.1));
+        assert!((rates[0] - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn report_compares_achieved_with_target() {
+        let (tiers, tokens) = tier_set(250);
+        let plan = CyclePlan::build(&tokens, &weights(&tiers.rates(&tokens, 20.0, None)), &[20], &[1]);
+        let report = tiers.report(&plan, &tokens);
+        let hot = report.iter().find(|r| r.name == "hot").unwrap();
+        // 周期 = (2×4 + 10) 批 / 20 rps = 900ms，热 token 每周期 4 次
+        assert_eq!((hot.tokens, hot.target_ms, hot.achieved_ms, hot.satisfied), (2, Some(250), 225, true));
+        let default = report.iter().find(|r| r.name == "default").unwrap();
+        assert_eq!((default.tokens, default.target_ms, default.achieved_ms, default.satisfied), (10, None, 900, true));
+
+        let (tiers, tokens) = tier_set(100);
+        let plan = CyclePlan::build(&tokens, &weights(&tiers.rates(&tokens, 20.0, None)), &[20], &[1]);
+        let hot = tiers.report(&plan, &tokens).into_iter().find(|r| r.name == "hot").unwrap();
+        assert_eq!((hot.target_ms, hot.achieved_ms, hot.satisfied), (Some(100), 600, false));
+    }
+}
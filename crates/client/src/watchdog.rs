@@ -0,0 +1,177 @@
+use crate::lease::Role;
+use crate::plan::Topology;
+use poly_ob_common::redisx::RedisClient;
+use poly_ob_common::settings::WatchdogConfig;
+use poly_ob_common::types::{StaleAlert, StaleToken};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+/// 周期巡检追踪中 token 的快照新鲜度：超过 `stale_ms` 未确认新鲜的 token 发布告警，
+/// 并交给调度器在接下来的派发中优先抓取。只有 leader 巡检，避免热备实例重复告警；
+/// 隔离中的 token 不在计划内，不巡检
+pub async fn watchdog_loop(
+    mut redis: RedisClient,
+    cfg: WatchdogConfig,
+    topo_rx: watch::Receiver<Topology>,
+    role_rx: watch::Receiver<Role>,
+    quarantine_rx: watch::Receiver<HashSet<String>>,
+    urgent_tx: mpsc::UnboundedSender<Vec<String>>,
+) {
+    let mut tick = tokio::time::interval(Duration::from_millis(cfg.interval_ms.max(100)));
+    let threshold = cfg.stale_ms as i64;
+    // 尚无快照的 token 从首次被巡检起计时，给新加入的 token 留出首轮抓取时间
+    let mut first_seen: HashMap<String, i64> = HashMap::new();
+    let mut stale: HashSet<String> = HashSet::new();
+    info!(stale_ms = cfg.stale_ms, channel = %cfg.channel, "staleness watchdog enabled");
+    loop {
+        tick.tick().await;
+        if !role_rx.borrow().is_leader() {
+            continue;
+        }
+        let tokens: Vec<String> = {
+            let quarantined = quarantine_rx.borrow();
+            // 被隔离的 token 不再算作陈旧，也不报告为恢复
+            stale.retain(|t| !quarantined.contains(t));
+            topo_rx.borrow().tokens.iter().filter(|t| !quarantined.contains(*t)).cloned().collect()
+        };
+        let fresh = match redis.freshness(&tokens).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("watchdog scan failed: {}", e);
+                continue;
+            }
+        };
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let tracked: HashSet<&String> = tokens.iter().collect();
+        first_seen.retain(|t, _| tracked.contains(t));
+        let mut now_stale = Vec::new();
+        let mut newly = Vec::new();
+        for (t, last) in tokens.iter().zip(fresh) {
+            let since = last.unwrap_or_else(|| *first_seen.entry(t.clone()).or_insert(now_ms));
+            let age_ms = now_ms - since;
+            if age_ms <= threshold {
+                continue;
+            }
+            if !stale.contains(t) {
+                newly.push(StaleToken { token_id: t.clone(), last_fresh_ms: last, age_ms });
+            }
+            now_stale.push(t.clone());
+        }
+        let current: HashSet<String> = now_stale.iter().cloned().collect();
+        let recovered: Vec<String> = stale.difference(&current).cloned().collect();
+        stale = current;
+
+        if !newly.is_empty() || !recovered.is_empty() {
+            warn!(newly = newly.len(), recovered = recovered.len(), stale = stale.len(), "stale snapshots");
+            let alert = StaleAlert { at_ms: now_ms, threshold_ms: cfg.stale_ms, stale: newly, recovered };
+            if let Err(e) = redis.publish_json(&cfg.channel, &alert).await {
+                warn!("stale alert publish failed: {}", e);
+            }
+        }
+        // 仍陈旧的 token 每轮都重新提交，直到恢复
+        if !now_stale.is_empty() && urgent_tx.send(now_stale).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::MockRedis;
+    use tokio::time::Instant;
+
+    const CHANNEL: &str = "ob_stale";
+
+    fn tokens(ts: &[&str]) -> Vec<String> {
+        ts.iter().map(|t| t.to_string()).collect()
+    }
+
+    struct Watch {
+        redis: MockRedis,
+        _topo: watch::Sender<Topology>,
+        role: watch::Sender<Role>,
+        quarantine: watch::Sender<HashSet<String>>,
+        urgent: mpsc::UnboundedReceiver<Vec<String>>,
+    }
+
+    /// 每 100ms 巡检一次，超过 300ms 未确认新鲜即陈旧
+    async fn start(redis: MockRedis, tracked: &[&str], role: Role) -> Watch {
+        let cfg = WatchdogConfig { enabled: true, interval_ms: 100, stale_ms: 300, channel: CHANNEL.into(), ..Default::default() };
+        let client = RedisClient::connect(&redis.url).await.unwrap();
+        let (topo_tx, topo_rx) = watch::channel(Topology { tokens: tokens(tracked), nodes: vec![] });
+        let (role_tx, role_rx) = watch::channel(role);
+        let (quarantine_tx, quarantine_rx) = watch::channel(HashSet::new());
+        let (urgent_tx, urgent) = mpsc::unbounded_channel();
+        tokio::spawn(watchdog_loop(client, cfg, topo_rx, role_rx, quarantine_rx, urgent_tx));
+        Watch { redis, _topo: topo_tx, role: role_tx, quarantine: quarantine_tx, urgent }
+    }
+
+    fn alerts(redis: &MockRedis) -> Vec<StaleAlert> {
+        redis.calls("PUBLISH").iter().filter(|c| c[1] == CHANNEL).map(|c| serde_json::from_str(&c[2]).unwrap()).collect()
+    }
+
+    async fn next_alert(redis: &MockRedis, seen: usize) -> StaleAlert {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            if let Some(a) = alerts(redis).into_iter().nth(seen) {
+                return a;
+            }
+            assert!(Instant::now() < deadline, "no alert #{}", seen);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    fn stale_ids(a: &StaleAlert) -> Vec<&str> {
+        a.stale.iter().map(|s| s.token_id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn alerts_on_stale_and_recovered_tokens_and_boosts_them() {
+        let redis = MockRedis::start().await;
+        let now = chrono::Utc::now().timestamp_millis();
+        redis.set_field("ob:fresh", "checked_at", now + 60_000);
+        redis.set_field("ob:old", "updated_at", now - 10_000);
+        redis.set_field("ob:old", "checked_at", now - 5_000);
+        let mut w = start(redis, &["fresh", "old", "new"], Role::Leader { fence: Some(1) }).await;
+
+        let a = next_alert(&w.redis, 0).await;
+        assert_eq!((stale_ids(&a), a.threshold_ms), (vec!["old"], 300));
+        assert_eq!(a.stale[0].last_fresh_ms, Some(now - 5_000), "取 updated_at 与 checked_at 中较新者");
+        assert!(a.recovered.is_empty());
+        assert_eq!(w.urgent.recv().await.unwrap(), ["old"]);
+
+        // 尚无快照的 token 从首次巡检起计时
+        let a = next_alert(&w.redis, 1).await;
+        assert_eq!(stale_ids(&a), ["new"]);
+        assert_eq!(a.stale[0].last_fresh_ms, None);
+        assert!(a.stale[0].age_ms > 300);
+
+        w.redis.set_field("ob:old", "checked_at", chrono::Utc::now().timestamp_millis() + 60_000);
+        let a = next_alert(&w.redis, 2).await;
+        assert!(a.stale.is_empty());
+        assert_eq!(a.recovered, ["old"]);
+    }
+
+    #[tokio::test]
+    async fn skips_quarantined_tokens_and_standby() {
+        let redis = MockRedis::start().await;
+        redis.set_field("ob:bad", "updated_at", 1);
+        redis.set_field("ob:old", "updated_at", 1);
+        let w = start(redis, &["bad", "old"], Role::Standby).await;
+        w.quarantine.send_replace(HashSet::from(["bad".to_string()]));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(w.redis.calls("HMGET").is_empty(), "备机不巡检");
+
+        w.role.send_replace(Role::Leader { fence: Some(1) });
+        assert_eq!(stale_ids(&next_alert(&w.redis, 0).await), ["old"]);
+        assert!(w.redis.calls("HMGET").iter().all(|c| c[1] != "ob:bad"));
+
+        // 陈旧中的 token 被隔离后不再报告，也不算恢复
+        w.quarantine.send_replace(HashSet::from(["bad".to_string(), "old".to_string()]));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(alerts(&w.redis).len(), 1);
+    }
+}
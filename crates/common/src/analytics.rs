@@ -0,0 +1,152 @@
+use crate::decimal::Decimal;
+use crate::settings::AnalyticsConfig;
+use crate::types::{BookLevel, OrderBookSnapshot};
+use serde::{Deserialize, Serialize};
+
+/// 由快照派生的簿面指标；一侧为空或累计溢出时依赖该侧的指标为 `None`。
+/// 价位顺序沿用快照约定：最优价在末尾
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookStats {
+    pub best_bid: Option<Decimal>,
+    pub best_ask: Option<Decimal>,
+    pub mid: Option<Decimal>,
+    pub spread: Option<Decimal>,
+    /// 价差折合的 tick 数；缺少 `tick_size` 或价差不在 tick 网格上时为 `None`
+    pub spread_ticks: Option<i64>,
+    /// 按最优档挂单量加权的中间价，偏向挂单较薄的一侧
+    pub microprice: Option<f64>,
+    /// 最优 `levels` 档的累计挂单量
+    pub bid_depth: Option<Decimal>,
+    pub ask_depth: Option<Decimal>,
+    /// 距中间价 `band` 以内的累计挂单量
+    pub bid_depth_band: Option<Decimal>,
+    pub ask_depth_band: Option<Decimal>,
+    /// `(bid_depth - ask_depth) / (bid_depth + ask_depth)`，取值 [-1, 1]，正值表示买盘更厚
+    pub imbalance: Option<f64>,
+}
+
+impl BookStats {
+    /// `levels` 为累计深度的档数，`band` 为按距中间价统计深度的价格范围
+    pub fn compute(ob: &OrderBookSnapshot, levels: usize, band: Decimal) -> Self {
+        let (best_bid, best_ask) = (best(&ob.bids), best(&ob.asks));
+        let mid = best_bid.zip(best_ask).and_then(|(b, a)| b.price.midpoint(a.price));
+        let spread = best_bid.zip(best_ask).and_then(|(b, a)| a.price.checked_sub(b.price));
+        let (bid_depth, ask_depth) = (depth(&ob.bids, levels), depth(&ob.asks, levels));
+        Self {
+            best_bid: best_bid.map(|l| l.price),
+            best_ask: best_ask.map(|l| l.price),
+            mid,
+            spread,
+            spread_ticks: spread.and_then(|s| ob.price_ticks(s)),
+            microprice: best_bid.zip(best_ask).and_then(|(b, a)| microprice(b, a)),
+            bid_depth,
+            ask_depth,
+            bid_depth_band: mid.and_then(|m| m.checked_sub(band)).and_then(|lo| depth_while(&ob.bids, |p| p >= lo)),
+            ask_depth_band: mid.and_then(|m| m.checked_add(band)).and_then(|hi| depth_while(&ob.asks, |p| p <= hi)),
+            imbalance: bid_depth.zip(ask_depth).and_then(|(b, a)| imbalance(b, a)),
+        }
+    }
+
+    /// 按配置计算；未开启时为 `None`
+    pub fn configured(cfg: &AnalyticsConfig, ob: &OrderBookSnapshot) -> Option<Self> {
+        cfg.enabled.then(|| Self::compute(ob, cfg.depth_levels, cfg.band()))
+    }
+
+    /// 写入 Redis 的字段；`None` 记为空串，由 CAS 脚本删除该字段
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let opt = |v: Option<Decimal>| v.map(|d| d.to_string()).unwrap_or_default();
+        let ratio = |v: Option<f64>| v.map(|x| format!("{:.6}", x)).unwrap_or_default();
+        vec![
+            ("best_bid", opt(self.best_bid)),
+            ("best_ask", opt(self.best_ask)),
+            ("mid", opt(self.mid)),
+            ("spread", opt(self.spread)),
+            ("spread_ticks", self.spread_ticks.map(|t| t.to_string()).unwrap_or_default()),
+            ("microprice", ratio(self.microprice)),
+            ("bid_depth", opt(self.bid_depth)),
+            ("ask_depth", opt(self.ask_depth)),
+            ("bid_depth_band", opt(self.bid_depth_band)),
+            ("ask_depth_band", opt(self.ask_depth_band)),
+            ("imbalance", ratio(self.imbalance)),
+        ]
+    }
+}
+
+/// 一侧的最优档（末尾）
+pub fn best(levels: &[BookLevel]) -> Option<&BookLevel> {
+    levels.last()
+}
+
+/// 最优 `n` 档的累计挂单量；溢出时为 `None`
+pub fn depth(levels: &[BookLevel], n: usize) -> Option<Decimal> {
+    sum(levels.iter().rev().take(n))
+}
+
+/// 从最优档起、价格满足 `within` 的连续各档累计挂单量；溢出时为 `None`
+pub fn depth_while(levels: &[BookLevel], within: impl Fn(Decimal) -> bool) -> Option<Decimal> {
+    sum(levels.iter().rev().take_while(|l| within(l.price)))
+}
+
+fn sum<'a>(mut levels: impl Iterator<Item = &'a BookLevel>) -> Option<Decimal> {
+    levels.try_fold(Decimal::ZERO, |acc, l| acc.checked_add(l.size))
+}
+
+/// `(bid × ask_size + ask × bid_size) / (bid_size + ask_size)`
+pub fn microprice(bid: &BookLevel, ask: &BookLevel) -> Option<f64> {
+    let (bs, as_) = (bid.size.to_f64(), ask.size.to_f64());
+    (bs + as_ > 0.0).then(|| (bid.price.to_f64() * as_ + ask.price.to_f64() * bs) / (bs + as_))
+}
+
+pub fn imbalance(bid_depth: Decimal, ask_depth: Decimal) -> Option<f64> {
+    let (b, a) = (bid_depth.to_f64(), ask_depth.to_f64());
+    (b + a > 0.0).then(|| (b - a) / (b + a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: &str, size: &str) -> BookLevel {
+        BookLevel { price: price.parse().unwrap(), size: size.parse().unwrap() }
+    }
+
+    fn snapshot(bids: Vec<BookLevel>, asks: Vec<BookLevel>) -> OrderBookSnapshot {
+        OrderBookSnapshot {
+            market: "m".into(),
+            asset_id: "a".into(),
+            hash: "h".into(),
+            timestamp: "1".into(),
+            bids,
+            asks,
+            min_order_size: None,
+            neg_risk: None,
+            tick_size: Some("0.01".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn computes_depth_and_imbalance() {
+        let ob = snapshot(
+            vec![level("0.40", "100"), level("0.47", "30"), level("0.48", "10")],
+            vec![level("0.60", "100"), level("0.52", "20"), level("0.50", "10")],
+        );
+        let s = BookStats::compute(&ob, 2, "0.05".parse().unwrap());
+        assert_eq!(s.mid, Some("0.49".parse().unwrap()));
+        assert_eq!(s.spread_ticks, Some(2));
+        assert_eq!((s.bid_depth, s.ask_depth), (Some("40".parse().unwrap()), Some("30".parse().unwrap())));
+        assert_eq!((s.bid_depth_band, s.ask_depth_band), (Some("40".parse().unwrap()), Some("30".parse().unwrap())));
+        assert!((s.imbalance.unwrap() - 10.0 / 70.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn depth_overflow_is_none_instead_of_panicking() {
+        let huge = i64::MAX.to_string();
+        let ob = snapshot(vec![level("0.47", &huge), level("0.48", &huge)], vec![level("0.50", "10")]);
+        let s = BookStats::compute(&ob, 5, "0.05".parse().unwrap());
+        assert_eq!((s.bid_depth, s.bid_depth_band, s.imbalance), (None, None, None));
+        assert_eq!(s.ask_depth, Some("10".parse().unwrap()));
+        let fields: std::collections::HashMap<_, _> = s.fields().into_iter().collect();
+        assert_eq!((fields["bid_depth"].as_str(), fields["imbalance"].as_str()), ("", ""));
+        assert_eq!(fields["ask_depth"], "10");
+    }
+}
@@ -0,0 +1,393 @@
+use crate::decimal::Decimal;
+use crate::types::{BookLevel, OrderBookSnapshot};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// 增量未带 hash 时以时间戳生成的占位 hash 前缀
+const SYNTHETIC_HASH_PREFIX: &str = "ws:";
+
+/// 增量所在的一侧：`BUY` 对应 bids，`SELL` 对应 asks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// 单个价位的增量：该价位的新挂单量，`0` 表示撤掉该价位。
+/// `hash` / `best_bid` / `best_ask` 为服务端给出的变更后状态，存在时用于校验
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelDelta {
+    pub side: Side,
+    pub price: Decimal,
+    pub size: Decimal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_bid: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_ask: Option<Decimal>,
+}
+
+/// 撤销一次 [`LocalBook::apply`] 所需的信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undo {
+    side: Side,
+    price: Decimal,
+    /// 变更前该价位的记录；`None` 表示此前没有该价位
+    prev: Option<BookLevel>,
+    /// 变更前该价位在一侧中的位置
+    index: usize,
+}
+
+/// 本地簿与增量流对不上，需要重新拉取快照
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Gap {
+    /// 变更后的最优价与服务端给出的不一致
+    #[error("best {side:?} mismatch: local {local:?}, remote {remote}")]
+    BestPrice { side: Side, local: Option<Decimal>, remote: Decimal },
+    /// 变更后重算的 hash 与服务端给出的不一致
+    #[error("hash mismatch: local {local}, remote {remote}")]
+    Hash { local: String, remote: String },
+}
+
+/// 可应用 `price_change` 增量的本地订单簿；价位顺序与 REST 快照一致：
+/// bids 价格升序、asks 价格降序（最优价都在末尾）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalBook {
+    pub market: String,
+    pub asset_id: String,
+    pub timestamp: String,
+    pub hash: String,
+    pub bids: Vec<BookLevel>,
+    pub asks: Vec<BookLevel>,
+    pub min_order_size: Option<Decimal>,
+    pub neg_risk: Option<bool>,
+    pub tick_size: Option<Decimal>,
+}
+
+impl LocalBook {
+    pub fn from_snapshot(ob: &OrderBookSnapshot) -> Self {
+        Self {
+            market: ob.market.clone(),
+            asset_id: ob.asset_id.clone(),
+            timestamp: ob.timestamp.clone(),
+            hash: ob.hash.clone(),
+            bids: ob.bids.clone(),
+            asks: ob.asks.clone(),
+            min_order_size: ob.min_order_size,
+            neg_risk: ob.neg_risk,
+            tick_size: ob.tick_size,
+        }
+    }
+
+    pub fn to_snapshot(&self) -> OrderBookSnapshot {
+        OrderBookSnapshot {
+            market: self.market.clone(),
+            asset_id: self.asset_id.clone(),
+            hash: self.hash.clone(),
+            timestamp: self.timestamp.clone(),
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+            min_order_size: self.min_order_size,
+            neg_risk: self.neg_risk,
+            tick_size: self.tick_size,
+        }
+    }
+
+    /// 价位内容与快照一致（按数值比较，不比较 hash 与时间戳）
+    pub fn same_levels(&self, ob: &OrderBookSnapshot) -> bool {
+        self.bids == ob.bids && self.asks == ob.asks
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut Vec<BookLevel> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    /// 最优买价（bids 末尾）
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.last().map(|l| l.price)
+    }
+
+    /// 最优卖价（asks 末尾）
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.last().map(|l| l.price)
+    }
+
+    /// 应用单个价位增量（不校验），返回撤销信息
+    pub fn apply(&mut self, d: &LevelDelta) -> Undo {
+        let levels = self.side_mut(d.side);
+        let pos = levels.iter().position(|l| l.price == d.price);
+        let undo = |prev: Option<BookLevel>, index| Undo { side: d.side, price: d.price, prev, index };
+        match (pos, d.size.is_zero()) {
+            (Some(i), true) => undo(Some(levels.remove(i)), i),
+            (Some(i), false) => {
+                let prev = levels[i].clone();
+                levels[i].size = d.size;
+                undo(Some(prev), i)
+            }
+            (None, true) => undo(None, levels.len()),
+            (None, false) => {
+                let ascending = d.side == Side::Buy;
+                let at = levels
+                    .iter()
+                    .position(|l| if ascending { l.price > d.price } else { l.price < d.price })
+                    .unwrap_or(levels.len());
+                levels.insert(at, BookLevel { price: d.price, size: d.size });
+                undo(None, at)
+            }
+        }
+    }
+
+    /// 撤销一次 `apply`；多次应用须按相反顺序撤销
+    pub fn rollback(&mut self, undo: Undo) {
+        let levels = self.side_mut(undo.side);
+        let pos = levels.iter().position(|l| l.price == undo.price);
+        match (pos, undo.prev) {
+            (Some(i), Some(prev)) => levels[i] = prev,
+            (Some(i), None) => {
+                levels.remove(i);
+            }
+            (None, Some(prev)) => levels.insert(undo.index.min(levels.len()), prev),
+            (None, None) => {}
+        }
+    }
+
+    /// 应用一条 `price_change` 中属于本 token 的增量：逐个应用并按服务端给出的最优价、hash 校验
+    /// （`verify_hash` 为真且本地掌握计算 hash 所需的全部字段时）。
+    /// 任一校验失败即整体回滚并返回 [`Gap`]，调用方应丢弃本地簿并重新拉取快照。
+    /// 时间戳早于本地簿的增量（本地簿已被更新的快照替换）已体现在簿面中，跳过并返回 `Ok(false)`
+    pub fn apply_update(&mut self, timestamp: &str, deltas: &[LevelDelta], verify_hash: bool) -> Result<bool, Gap> {
+        if matches!((timestamp.parse::<i64>(), self.timestamp.parse::<i64>()), (Ok(t), Ok(cur)) if t < cur) {
+            return Ok(false);
+        }
+        let (prev_ts, prev_hash) = (std::mem::replace(&mut self.timestamp, timestamp.to_string()), self.hash.clone());
+        let mut undos = Vec::with_capacity(deltas.len());
+        for d in deltas {
+            undos.push(self.apply(d));
+            if let Err(gap) = self.verify(d, verify_hash) {
+                for u in undos.into_iter().rev() {
+                    self.rollback(u);
+                }
+                self.timestamp = prev_ts;
+                self.hash = prev_hash;
+                return Err(gap);
+            }
+            // 缺少 hash 时以时间戳区分版本，避免下游 CAS 误判为未变化
+            self.hash = d.hash.clone().unwrap_or_else(|| format!("{}{}", SYNTHETIC_HASH_PREFIX, timestamp));
+        }
+        Ok(true)
+    }
+
+    /// 当前 hash 是否为增量缺少 hash 时生成的占位值；占位值不能与服务端 hash 比较
+    pub fn has_synthetic_hash(&self) -> bool {
+        self.hash.starts_with(SYNTHETIC_HASH_PREFIX)
+    }
+
+    fn verify(&self, d: &LevelDelta, verify_hash: bool) -> Result<(), Gap> {
+        for (side, local, remote) in [(Side::Buy, self.best_bid(), &d.best_bid), (Side::Sell, self.best_ask(), &d.best_ask)] {
+            let Some(remote) = *remote else { continue };
+            // 服务端以 "0" 表示该侧为空
+            let ok = match local {
+                Some(l) => l == remote,
+                None => remote.is_zero(),
+            };
+            if !ok {
+                return Err(Gap::BestPrice { side, local, remote });
+            }
+        }
+        if let (true, Some(remote)) = (verify_hash, &d.hash) {
+            if let Some(local) = self.summary_hash() {
+                if &local != remote {
+                    return Err(Gap::Hash { local, remote: remote.clone() });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 按 py-clob-client `generate_orderbook_summary_hash` 的算法重算 hash：
+    /// 以空 hash 序列化簿面摘要（紧凑 JSON，字段顺序固定）后取 SHA-1。
+    /// 缺少 `min_order_size` / `neg_risk` / `tick_size`（仅由 REST 快照提供）时无法计算，返回 `None`
+    pub fn summary_hash(&self) -> Option<String> {
+        #[derive(Serialize)]
+        struct Summary<'a> {
+            market: &'a str,
+            asset_id: &'a str,
+            timestamp: &'a str,
+            bids: &'a [BookLevel],
+            asks: &'a [BookLevel],
+            min_order_size: Decimal,
+            neg_risk: bool,
+            tick_size: Decimal,
+            hash: &'a str,
+        }
+        let summary = Summary {
+            market: &self.market,
+            asset_id: &self.asset_id,
+            timestamp: &self.timestamp,
+            bids: &self.bids,
+            asks: &self.asks,
+            min_order_size: self.min_order_size?,
+            neg_risk: self.neg_risk?,
+            tick_size: self.tick_size?,
+            hash: "",
+        };
+        let json = serde_json::to_vec(&summary).ok()?;
+        Some(format!("{:x}", Sha1::digest(&json)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn level(price: &str, size: &str) -> BookLevel {
+        BookLevel { price: dec(price), size: dec(size) }
+    }
+
+    fn delta(side: Side, price: &str, size: &str) -> LevelDelta {
+        LevelDelta { side, price: dec(price), size: dec(size), hash: None, best_bid: None, best_ask: None }
+    }
+
+    fn book() -> LocalBook {
+        LocalBook {
+            market: "m".into(),
+            asset_id: "a".into(),
+            timestamp: "100".into(),
+            hash: "h0".into(),
+            bids: vec![level("0.48", "10"), level("0.49", "5")],
+            asks: vec![level("0.52", "7"), level("0.51", "3")],
+            min_order_size: Some(dec("5")),
+            neg_risk: Some(false),
+            tick_size: Some(dec("0.01")),
+        }
+    }
+
+    fn assert_sorted(b: &LocalBook) {
+        assert!(b.bids.windows(2).all(|w| w[0].price < w[1].price), "bids not ascending: {:?}", b.bids);
+        assert!(b.asks.windows(2).all(|w| w[0].price > w[1].price), "asks not descending: {:?}", b.asks);
+    }
+
+    /// 价格取同一数值的不同小数位数（0.5 / 0.50），覆盖按数值匹配价位
+    fn random_delta(rng: &mut StdRng) -> LevelDelta {
+        let side = if rng.gen() { Side::Buy } else { Side::Sell };
+        let cents: i64 = rng.gen_range(1..30);
+        let price = match rng.gen_bool(0.2) {
+            true => Decimal::new(cents * 10, 3),
+            false => Decimal::new(cents, 2),
+        };
+        let size = Decimal::new(rng.gen_range(0..4) * 25, 1);
+        LevelDelta { side, price, size, hash: None, best_bid: None, best_ask: None }
+    }
+
+    #[test]
+    fn random_deltas_roll_back_exactly_and_keep_sides_sorted() {
+        for seed in 0..500 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut b = book();
+            for _ in 0..rng.gen_range(0..20) {
+                b.apply(&random_delta(&mut rng));
+            }
+            let before = b.clone();
+            let mut undos = Vec::new();
+            for _ in 0..rng.gen_range(1..40) {
+                undos.push(b.apply(&random_delta(&mut rng)));
+                assert_sorted(&b);
+            }
+            for u in undos.into_iter().rev() {
+                b.rollback(u);
+                assert_sorted(&b);
+            }
+            assert_eq!(b, before, "seed {}", seed);
+            // 价位原始写法（小数位数）也须还原
+            assert_eq!(format!("{:?}", b.bids), format!("{:?}", before.bids), "seed {}", seed);
+            assert_eq!(format!("{:?}", b.asks), format!("{:?}", before.asks), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn apply_keeps_levels_sorted() {
+        let cases: &[(LevelDelta, &[&str], &[&str])] = &[
+            (delta(Side::Buy, "0.47", "1"), &["0.47", "0.48", "0.49"], &["0.52", "0.51"]),
+            (delta(Side::Buy, "0.50", "1"), &["0.48", "0.49", "0.50"], &["0.52", "0.51"]),
+            (delta(Side::Buy, "0.49", "0"), &["0.48"], &["0.52", "0.51"]),
+            (delta(Side::Buy, "0.490", "8"), &["0.48", "0.49"], &["0.52", "0.51"]),
+            (delta(Side::Sell, "0.53", "1"), &["0.48", "0.49"], &["0.53", "0.52", "0.51"]),
+            (delta(Side::Sell, "0.50", "1"), &["0.48", "0.49"], &["0.52", "0.51", "0.50"]),
+            (delta(Side::Sell, "0.515", "1"), &["0.48", "0.49"], &["0.52", "0.515", "0.51"]),
+            (delta(Side::Sell, "0.60", "0"), &["0.48", "0.49"], &["0.52", "0.51"]),
+        ];
+        for (d, bids, asks) in cases {
+            let mut b = book();
+            b.apply(d);
+            let prices = |ls: &[BookLevel]| ls.iter().map(|l| l.price).collect::<Vec<_>>();
+            assert_eq!(prices(&b.bids), bids.iter().map(|p| dec(p)).collect::<Vec<_>>(), "{:?}", d);
+            assert_eq!(prices(&b.asks), asks.iter().map(|p| dec(p)).collect::<Vec<_>>(), "{:?}", d);
+        }
+    }
+
+    #[test]
+    fn mismatched_update_is_a_gap_and_leaves_book_unchanged() {
+        let with = |mut d: LevelDelta, f: fn(&mut LevelDelta)| {
+            f(&mut d);
+            d
+        };
+        let cases: Vec<(&str, Vec<LevelDelta>)> = vec![
+            ("best bid", vec![with(delta(Side::Buy, "0.50", "1"), |d| d.best_bid = Some(dec("0.49")))]),
+            ("best ask", vec![with(delta(Side::Sell, "0.51", "0"), |d| d.best_ask = Some(dec("0.51")))]),
+            ("empty side", vec![with(delta(Side::Sell, "0.51", "0"), |d| d.best_ask = Some(dec("0")))]),
+            ("hash", vec![with(delta(Side::Buy, "0.47", "2"), |d| d.hash = Some("bogus".into()))]),
+            (
+                "second of two",
+                vec![
+                    with(delta(Side::Buy, "0.50", "1"), |d| d.best_bid = Some(dec("0.5"))),
+                    with(delta(Side::Sell, "0.52", "0"), |d| d.best_ask = Some(dec("0.52"))),
+                ],
+            ),
+        ];
+        for (name, deltas) in cases {
+            let mut b = book();
+            let before = b.clone();
+            assert!(b.apply_update("101", &deltas, true).is_err(), "{}", name);
+            assert_eq!(b, before, "{}", name);
+            assert_eq!((b.timestamp.as_str(), b.hash.as_str()), ("100", "h0"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn matching_update_applies_and_takes_remote_hash() {
+        let mut b = book();
+        let mut d = delta(Side::Buy, "0.50", "1");
+        d.best_bid = Some(dec("0.5"));
+        d.best_ask = Some(dec("0.51"));
+        assert_eq!(b.apply_update("101", &[d.clone()], false), Ok(true));
+        assert_eq!((b.best_bid(), b.timestamp.as_str(), b.hash.as_str()), (Some(dec("0.50")), "101", "ws:101"));
+
+        // 本地重算的 hash 与服务端一致时通过校验
+        let mut next = b.clone();
+        next.apply(&delta(Side::Sell, "0.51", "4"));
+        next.timestamp = "102".into();
+        let mut d = delta(Side::Sell, "0.51", "4");
+        d.hash = next.summary_hash();
+        assert_eq!(b.apply_update("102", &[d], true), Ok(true));
+        assert_eq!(b.hash, next.summary_hash().unwrap());
+    }
+
+    #[test]
+    fn older_update_is_skipped() {
+        let mut b = book();
+        let d = delta(Side::Buy, "0.30", "1");
+        assert_eq!(b.apply_update("99", &[d], true), Ok(false));
+        assert_eq!(b, book());
+    }
+}
@@ -0,0 +1,324 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// 小数位数上限：对齐后 `i64 × 10^18` 仍在 `i128` 范围内
+const MAX_SCALE: u8 = 18;
+
+/// 定点小数 `mantissa × 10^-scale`。按数值比较（`0.5 == 0.50`），序列化为规范写法：
+/// 可选负号、整数部分与（有小数位时）小数部分。规范写法的输入保留小数位数原样往返（`"0.50"` → `"0.50"`，
+/// hash 按此计算）；其余写法（`.5`、`+0.5`、`5.`、指数、JSON 数字、超过 18 位的小数）规范化后不再等同原串
+#[derive(Clone, Copy)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecimalError {
+    #[error("invalid decimal {0:?}")]
+    Invalid(String),
+    #[error("decimal {0:?} out of range")]
+    Overflow(String),
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal { mantissa: 0, scale: 0 };
+
+    /// `scale` 超过 18 时截为 18
+    pub const fn new(mantissa: i64, scale: u8) -> Self {
+        Self { mantissa, scale: if scale > MAX_SCALE { MAX_SCALE } else { scale } }
+    }
+
+    pub fn mantissa(&self) -> i64 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    pub fn is_sign_negative(&self) -> bool {
+        self.mantissa < 0
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    /// 按 `scale` 放大到 `i128`，`scale` 不小于自身小数位数
+    fn widen(&self, scale: u8) -> i128 {
+        self.mantissa as i128 * 10i128.pow((scale - self.scale) as u32)
+    }
+
+    fn from_wide(v: i128, scale: u8) -> Option<Self> {
+        i64::try_from(v).ok().map(|mantissa| Self { mantissa, scale })
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let s = self.scale.max(other.scale);
+        Self::from_wide(self.widen(s) + other.widen(s), s)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let s = self.scale.max(other.scale);
+        Self::from_wide(self.widen(s) - other.widen(s), s)
+    }
+
+    /// 两数的中点，精确到多一位小数；溢出时为 `None`
+    pub fn midpoint(self, other: Self) -> Option<Self> {
+        let s = self.scale.max(other.scale);
+        let sum = self.widen(s) + other.widen(s);
+        match s < MAX_SCALE {
+            true => Self::from_wide(sum * 5, s + 1).map(|d| d.normalize()),
+            false if sum % 2 == 0 => Self::from_wide(sum / 2, s),
+            false => None,
+        }
+    }
+
+    /// 去掉末尾多余的 0（`0.510` → `0.51`）
+    pub fn normalize(self) -> Self {
+        let (mantissa, scale) = self.normalized();
+        Self { mantissa, scale }
+    }
+
+    /// 以 `tick` 为单位的整数倍数；不在 tick 网格上、`tick` 非正或溢出时为 `None`
+    pub fn ticks(&self, tick: Decimal) -> Option<i64> {
+        let s = self.scale.max(tick.scale);
+        let (v, t) = (self.widen(s), tick.widen(s));
+        if t <= 0 || v % t != 0 {
+            return None;
+        }
+        i64::try_from(v / t).ok()
+    }
+
+    /// 去掉末尾的 0，作为数值相等时的统一表示
+    fn normalized(&self) -> (i64, u8) {
+        let (mut m, mut s) = (self.mantissa, self.scale);
+        while s > 0 && m % 10 == 0 {
+            m /= 10;
+            s -= 1;
+        }
+        (m, s)
+    }
+}
+
+/// 接受常见写法：可带 `+`/`-` 号，整数或小数部分之一可省略（`.5`、`5.`），可带指数（`1e-3`）。
+/// 小数位数按十进制写法保留（`0.50` 的 scale 为 2）；超过 18 位时四舍五入到 18 位
+impl FromStr for Decimal {
+    type Err = DecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DecimalError::Invalid(s.to_string());
+        let overflow = || DecimalError::Overflow(s.to_string());
+        let (neg, body) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (num, exp) = match body.split_once(['e', 'E']) {
+            Some((num, exp)) => (num, exp.parse::<i32>().map_err(|_| invalid())?),
+            None => (body, 0),
+        };
+        let (int, frac) = num.split_once('.').unwrap_or((num, ""));
+        if int.len() + frac.len() == 0 || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let mut digits = format!("{}{}", int, frac);
+        let mut scale = frac.len() as i64 - exp as i64;
+        // 超出精度的位四舍五入
+        let mut round_up = false;
+        if scale > MAX_SCALE as i64 {
+            let cut = usize::try_from(scale - MAX_SCALE as i64).unwrap_or(usize::MAX);
+            let keep = digits.len().saturating_sub(cut);
+            // 舍去的最高位；舍去位数超过全部位数时结果为 0
+            round_up = cut <= digits.len() && digits.as_bytes()[keep] >= b'5';
+            digits.truncate(keep);
+            scale = MAX_SCALE as i64;
+        }
+        let digits = digits.trim_start_matches('0');
+        let mut v: i128 = match digits {
+            "" => 0,
+            d => d.parse().map_err(|_| overflow())?,
+        };
+        v += i128::from(round_up);
+        if scale < 0 {
+            if v != 0 {
+                v = 10i128.checked_pow((-scale) as u32).and_then(|p| v.checked_mul(p)).ok_or_else(overflow)?;
+            }
+            scale = 0;
+        }
+        let mantissa = i64::try_from(if neg { -v } else { v }).map_err(|_| overflow())?;
+        Ok(Self { mantissa, scale: scale as u8 })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.scale == 0 {
+            return write!(f, "{}", self.mantissa);
+        }
+        let pow = 10u64.pow(self.scale as u32);
+        let abs = self.mantissa.unsigned_abs();
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        write!(f, "{}{}.{:0width$}", sign, abs / pow, abs % pow, width = self.scale as usize)
+    }
+}
+
+impl fmt::Debug for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let s = self.scale.max(other.scale);
+        self.widen(s).cmp(&other.widen(s))
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized().hash(state);
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(v: i64) -> Self {
+        Self { mantissa: v, scale: 0 }
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalVisitor;
+
+        impl Visitor<'_> for DecimalVisitor {
+            type Value = Decimal;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal string or number")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+                Ok(v.into())
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+                i64::try_from(v).map(Decimal::from).map_err(|_| E::custom(DecimalError::Overflow(v.to_string())))
+            }
+
+            /// JSON 数字按最短往返写法（`0.1` 而非 `0.1000000000000000055…`）解析
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+                if !v.is_finite() {
+                    return Err(E::custom(DecimalError::Invalid(v.to_string())));
+                }
+                v.to_string().parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_common_forms() {
+        let cases = [
+            ("0.5", "0.5"),
+            ("0.50", "0.50"),
+            ("-0.5", "-0.5"),
+            ("+0.5", "0.5"),
+            (".5", "0.5"),
+            ("5.", "5"),
+            ("007", "7"),
+            ("1e-3", "0.001"),
+            ("1.5E3", "1500"),
+            ("2.5e+1", "25"),
+            ("2.50e-1", "0.250"),
+            ("0e100", "0"),
+            ("0.1234567890123456789", "0.123456789012345679"),
+            ("0.1234567890123456781", "0.123456789012345678"),
+            ("1e-30", "0.000000000000000000"),
+            ("9223372036854775807", "9223372036854775807"),
+        ];
+        for (input, want) in cases {
+            let d: Decimal = input.parse().unwrap_or_else(|e| panic!("{}: {}", input, e));
+            assert_eq!(d.to_string(), want, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_or_out_of_range() {
+        for input in ["", ".", "-", "e5", "1e", "1.2.3", "0x10", " 1", "1,5", "abc"] {
+            assert!(matches!(input.parse::<Decimal>(), Err(DecimalError::Invalid(_))), "{:?}", input);
+        }
+        for input in ["9223372036854775808", "1e19", "99999999999999999999.5"] {
+            assert!(matches!(input.parse::<Decimal>(), Err(DecimalError::Overflow(_))), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn deserializes_json_strings_and_numbers() {
+        let v: Vec<Decimal> = serde_json::from_str(r#"["0.50", 3, 0.1, 1e-3, 12.5]"#).unwrap();
+        let s: Vec<String> = v.iter().map(Decimal::to_string).collect();
+        assert_eq!(s, ["0.50", "3", "0.1", "0.001", "12.5"]);
+        assert_eq!(serde_json::to_string(&v[0]).unwrap(), r#""0.50""#);
+    }
+
+    #[test]
+    fn serializes_in_canonical_form() {
+        let json = r#"["0.50", "-0.010", "7", ".5", "+0.5", "5.", "1e-3", "2.50E1", 0.1, 12, 3.0]"#;
+        let v: Vec<Decimal> = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            serde_json::to_string(&v).unwrap(),
+            r#"["0.50","-0.010","7","0.5","0.5","5","0.001","25.0","0.1","12","3"]"#
+        );
+    }
+
+    #[test]
+    fn compares_numerically() {
+        let d = |s: &str| s.parse::<Decimal>().unwrap();
+        assert_eq!(d("0.5"), d("0.500"));
+        assert!(d("0.49") < d("0.5"));
+        assert_eq!(d("0.48").midpoint(d("0.5")), Some(d("0.49")));
+        assert_eq!(d("0.53").ticks(d("0.01")), Some(53));
+        assert_eq!(d("0.535").ticks(d("0.01")), None);
+        assert_eq!(Decimal::new(i64::MAX, 0).checked_add(d("1")), None);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::settings::RetryPolicy;
+use std::time::Duration;
+
+/// 非 2xx 响应，保留状态码与响应体
+#[derive(Debug, thiserror::Error)]
+#[error("{method} {path} {status}: {body}")]
+pub struct HttpStatusError {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub status: u16,
+    pub body: String,
+    /// 响应头 `Retry-After`（仅支持秒数与 HTTP 日期两种格式）
+    pub retry_after: Option<Duration>,
+}
+
+/// common 库（http / redisx / settings）的统一错误类型，调用方可据此区分
+/// payload 问题（4xx）、上游故障（5xx/429/网络）、Redis 故障与配置错误
+#[derive(Debug, thiserror::Error)]
+pub enum PolyObError {
+    #[error("connect failed: {0}")]
+    Connect(String),
+    #[error("request timed out: {0}")]
+    Timeout(String),
+    /// 429
+    #[error("rate limited: {0}")]
+    RateLimited(HttpStatusError),
+    /// 其他 4xx：请求本身有问题，重发无益
+    #[error(transparent)]
+    ClientStatus(HttpStatusError),
+    /// 5xx 及其他非 2xx
+    #[error(transparent)]
+    ServerStatus(HttpStatusError),
+    #[error("request failed: {0}")]
+    Request(String),
+    /// 还可重试，但下一次尝试已赶不上时限
+    #[error("deadline exceeded after {attempts} attempts: {last}")]
+    DeadlineExceeded { attempts: u32, last: Box<PolyObError> },
+    /// 响应体或存储内容无法解析
+    #[error("decode failed: {0}")]
+    Decode(String),
+    #[error("redis: {0}")]
+    Redis(#[from] redis::RedisError),
+    /// Lua 脚本返回了约定之外的结果
+    #[error("lua script {script} returned unexpected {outcome:?}")]
+    Script { script: &'static str, outcome: String },
+    #[error("config {path}: {source}")]
+    ConfigRead { path: String, source: std::io::Error },
+    #[error("config parse: {0}")]
+    ConfigParse(#[from] toml::de::Error),
+}
+
+pub type Result<T, E = PolyObError> = std::result::Result<T, E>;
+
+impl From<HttpStatusError> for PolyObError {
+    fn from(e: HttpStatusError) -> Self {
+        match e.status {
+            429 => Self::RateLimited(e),
+            400..=499 => Self::ClientStatus(e),
+            _ => Self::ServerStatus(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for PolyObError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e.to_string())
+        } else if e.is_connect() {
+            Self::Connect(format!("{:?}", e))
+        } else if e.is_decode() {
+            Self::Decode(e.to_string())
+        } else {
+            Self::Request(format!("{:?}", e))
+        }
+    }
+}
+
+impl From<serde_json::Error> for PolyObError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Decode(e.to_string())
+    }
+}
+
+impl PolyObError {
+    /// 上游 HTTP 响应的状态码（非 HTTP 状态错误为 `None`）
+    pub fn status(&self) -> Option<u16> {
+        self.http_status().map(|s| s.status)
+    }
+
+    fn http_status(&self) -> Option<&HttpStatusError> {
+        match self {
+            Self::RateLimited(s) | Self::ClientStatus(s) | Self::ServerStatus(s) => Some(s),
+            Self::DeadlineExceeded { last, .. } => last.http_status(),
+            _ => None,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.http_status().and_then(|s| s.retry_after)
+    }
+
+    /// 按策略判断该错误是否值得重试
+    pub fn retryable(&self, p: &RetryPolicy) -> bool {
+        match self {
+            Self::Connect(_) => p.on_connect,
+            Self::Timeout(_) => p.on_timeout,
+            Self::RateLimited(_) => p.on_429,
+            Self::ClientStatus(_) => p.on_4xx,
+            Self::ServerStatus(s) => (500..=599).contains(&s.status) && p.on_5xx,
+            _ => false,
+        }
+    }
+
+    /// 上游请求未能到达或未得到响应（连接失败、超时）
+    pub fn is_unreachable(&self) -> bool {
+        match self {
+            Self::Connect(_) | Self::Timeout(_) => true,
+            Self::DeadlineExceeded { last, .. } => last.is_unreachable(),
+            _ => false,
+        }
+    }
+
+    pub fn is_redis(&self) -> bool {
+        matches!(self, Self::Redis(_) | Self::Script { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16) -> PolyObError {
+        HttpStatusError { method: "POST", path: "/books", status, body: String::new(), retry_after: Some(Duration::from_secs(2)) }.into()
+    }
+
+    #[test]
+    fn http_status_maps_to_variant() {
+        assert!(matches!(status(429), PolyObError::RateLimited(_)));
+        assert!(matches!(status(400), PolyObError::ClientStatus(_)));
+        assert!(matches!(status(413), PolyObError::ClientStatus(_)));
+        assert!(matches!(status(503), PolyObError::ServerStatus(_)));
+        assert!(matches!(status(302), PolyObError::ServerStatus(_)));
+    }
+
+    #[test]
+    fn retryable_follows_policy() {
+        let p = RetryPolicy::default();
+        assert!(PolyObError::Connect("refused".into()).retryable(&p));
+        assert!(PolyObError::Timeout("slow".into()).retryable(&p));
+        assert!(status(429).retryable(&p));
+        assert!(status(502).retryable(&p));
+        assert!(!status(404).retryable(&p));
+        assert!(!status(302).retryable(&p), "非 5xx 的其他状态不重试");
+        assert!(!PolyObError::Decode("bad json".into()).retryable(&p));
+        assert!(!PolyObError::Request("builder".into()).retryable(&p));
+
+        let p = RetryPolicy { on_4xx: true, on_429: false, on_5xx: false, on_connect: false, on_timeout: false, ..p };
+        assert!(status(404).retryable(&p));
+        assert!(!status(429).retryable(&p));
+        assert!(!status(502).retryable(&p));
+        assert!(!PolyObError::Connect("refused".into()).retryable(&p));
+        assert!(!PolyObError::Timeout("slow".into()).retryable(&p));
+    }
+
+    #[test]
+    fn unreachable_and_status_see_through_deadline() {
+        let deadline = |last| PolyObError::DeadlineExceeded { attempts: 3, last: Box::new(last) };
+        assert!(PolyObError::Connect("refused".into()).is_unreachable());
+        assert!(deadline(PolyObError::Timeout("slow".into())).is_unreachable());
+        assert!(!deadline(status(503)).is_unreachable());
+        assert!(!status(503).is_unreachable());
+        assert_eq!(deadline(status(503)).status(), Some(503));
+        assert_eq!(deadline(status(429)).retry_after(), Some(Duration::from_secs(2)));
+        assert_eq!(PolyObError::Timeout("slow".into()).status(), None);
+        assert!(PolyObError::Script { script: "cas_update", outcome: "x".into() }.is_redis());
+        assert!(!status(500).is_redis());
+    }
+
+    #[tokio::test]
+    async fn reqwest_errors_are_classified() {
+        // 绑定后不 accept：连接进入 backlog，请求得不到响应
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent = format!("http://{}", listener.local_addr().unwrap());
+        let client = reqwest::Client::new();
+        let err: PolyObError = client.get(&silent).timeout(Duration::from_millis(50)).send().await.unwrap_err().into();
+        assert!(matches!(err, PolyObError::Timeout(_)), "{:?}", err);
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let err: PolyObError = client.get(format!("http://{}", closed)).send().await.unwrap_err().into();
+        assert!(matches!(err, PolyObError::Connect(_)), "{:?}", err);
+        assert!(err.is_unreachable());
+    }
+}
@@ -1,13 +1,40 @@
-use anyhow::Result;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
 use reqwest::{Client, ClientBuilder};
 use std::time::Duration;
+use tokio::time::Instant;
 
-use crate::types::{BookTokenParam, OrderBookSnapshot};
+use crate::error::{HttpStatusError, PolyObError, Result};
+use crate::settings::{MarketFilter, RetryPolicy};
+use crate::types::{BookTokenParam, MarketsPage, OrderBookSnapshot};
+
+/// `/markets` 分页结束标记
+pub const MARKETS_END_CURSOR: &str = "LTE=";
+/// 未单独指定超时的请求（`/book`、`/markets`）的总超时
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 第 `attempt` 次失败后的等待时长：指数退避，封顶后按 `jitter` 随机缩短
+fn backoff(p: &RetryPolicy, attempt: u32) -> Duration {
+    let exp = p.base_backoff_ms.saturating_mul(1u64 << attempt.saturating_sub(1).min(20)).min(p.max_backoff_ms);
+    let jitter = p.jitter.clamp(0.0, 1.0);
+    let factor = 1.0 - jitter * rand::thread_rng().gen::<f64>();
+    Duration::from_millis((exp as f64 * factor) as u64)
+}
+
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let v = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = v.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(v).ok()?;
+    (at.to_utc() - chrono::Utc::now()).to_std().ok()
+}
 
 #[derive(Clone)]
 pub struct HttpClient {
     inner: Client,
     base: String,
+    retry: RetryPolicy,
 }
 
 impl HttpClient {
@@ -18,11 +45,18 @@ impl HttpClient {
             .pool_max_idle_per_host(50)
             .tcp_keepalive(Duration::from_secs(30))
             .connect_timeout(Duration::from_millis(1200))
+            .timeout(REQUEST_TIMEOUT)
             .gzip(true)
             .brotli(true)
             .deflate(true)
             .build()?;
-        Ok(Self { inner, base: base.into() })
+        Ok(Self { inner, base: base.into(), retry: RetryPolicy::default() })
+    }
+
+    /// 替换 `get_books` 的重试策略
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
     }
 
     pub async fn get_book(&self, token_id: &str) -> Result<OrderBookSnapshot> {
@@ -30,18 +64,94 @@ impl HttpClient {
         let resp = self.inner.get(&url).send().await?;
         let status = resp.status();
         if !status.is_success() {
-            anyhow::bail!("GET /book {}", status);
+            let body = resp.text().await.unwrap_or_default();
+            return Err(HttpStatusError { method: "GET", path: "/book", status: status.as_u16(), body, retry_after: None }.into());
         }
         Ok(resp.json::<OrderBookSnapshot>().await?)
     }
 
+    /// 拉取一页市场列表；首页传空游标
+    pub async fn get_markets(&self, cursor: &str) -> Result<MarketsPage> {
+        let resp = self.inner.get(format!("{}/markets", self.base)).query(&[("next_cursor", cursor)]).send().await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            let err = HttpStatusError { method: "GET", path: "/markets", status: status.as_u16(), body, retry_after: None };
+            return Err(err.into());
+        }
+        Ok(resp.json::<MarketsPage>().await?)
+    }
+
+    /// 翻页遍历市场列表，返回满足过滤条件的市场下全部 tokenId（去重、排序）
+    pub async fn discover_tokens(&self, filter: &MarketFilter) -> Result<Vec<String>> {
+        let mut cursor = String::new();
+        let mut pages = 0;
+        let mut tokens = Vec::new();
+        loop {
+            let page = self.get_markets(&cursor).await?;
+            pages += 1;
+            for m in page.data.iter().filter(|m| m.matches(filter)) {
+                tokens.extend(m.tokens.iter().filter(|t| !t.token_id.is_empty()).map(|t| t.token_id.clone()));
+            }
+            let next = page.next_cursor.unwrap_or_default();
+            // 游标不前进时同样停止，避免异常响应导致死循环
+            if next.is_empty() || next == MARKETS_END_CURSOR || next == cursor || filter.max_pages.is_some_and(|m| pages >= m) {
+                break;
+            }
+            cursor = next;
+        }
+        tokens.sort();
+        tokens.dedup();
+        Ok(tokens)
+    }
+
+    /// 批量抓取订单簿，按策略重试，总时长不超过 `budget_ms`
     pub async fn get_books(&self, token_ids: &[String]) -> Result<Vec<OrderBookSnapshot>> {
+        let deadline = Instant::now() + Duration::from_millis(self.retry.budget_ms);
+        self.get_books_with(token_ids, deadline, || true).await
+    }
+
+    /// 批量抓取订单簿：可重试的失败（见 [`RetryPolicy`]）按指数退避加抖动重试，优先遵循 `Retry-After`（不超过 `max_backoff_ms`）；
+    /// 每次尝试的超时不超过剩余时限，下一次尝试赶不上 `deadline` 时返回 [`PolyObError::DeadlineExceeded`]。
+    /// 每次重试前调用 `permit` 申请额度（如节点限速），返回 `false` 时放弃重试并返回本次错误
+    pub async fn get_books_with(
+        &self,
+        token_ids: &[String],
+        deadline: Instant,
+        mut permit: impl FnMut() -> bool,
+    ) -> Result<Vec<OrderBookSnapshot>> {
         // POST /books with raw array body: [{ "token_id": "..." }, ...]
-        let url = format!("{}/books", self.base);
         let body: Vec<BookTokenParam> = token_ids
             .iter()
             .map(|t| BookTokenParam { token_id: t.clone() })
             .collect();
+        let p = &self.retry;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let err = match self.post_books(&body, remaining).await {
+                Ok(books) => return Ok(books),
+                Err(e) => e,
+            };
+            if attempt >= p.max_attempts.max(1) || !err.retryable(p) {
+                return Err(err);
+            }
+            let max = Duration::from_millis(p.max_backoff_ms);
+            let delay = err.retry_after().map(|d| d.min(max)).unwrap_or_else(|| backoff(p, attempt));
+            if Instant::now() + delay >= deadline {
+                return Err(PolyObError::DeadlineExceeded { attempts: attempt, last: Box::new(err) });
+            }
+            tracing::warn!(attempt, delay_ms = delay.as_millis() as u64, err = %err, "POST /books failed, retrying");
+            tokio::time::sleep(delay).await;
+            if !permit() {
+                return Err(err);
+            }
+        }
+    }
+
+    async fn post_books(&self, body: &[BookTokenParam], timeout: Duration) -> Result<Vec<OrderBookSnapshot>> {
+        let url = format!("{}/books", self.base);
         let resp = self
             .inner
             .post(&url)
@@ -53,17 +163,297 @@ impl HttpClient {
             .header("Accept-Language", "en-US,en;q=0.9")
             .header("Origin", &self.base)
             .header("Referer", format!("{}/", &self.base))
-            .json(&body)
+            .timeout(timeout)
+            .json(body)
             .send()
-            .await
-            .map_err(|e| anyhow::anyhow!("send POST /books failed: {:?}", e))?;
+            .await?;
         let status = resp.status();
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
-            anyhow::bail!("POST /books {}: {}", status, text);
+            let retry_after = parse_retry_after(resp.headers());
+            let body = resp.text().await.unwrap_or_default();
+            return Err(HttpStatusError { method: "POST", path: "/books", status: status.as_u16(), body, retry_after }.into());
         }
         Ok(resp.json::<Vec<OrderBookSnapshot>>().await?)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
 
+    /// 按请求的游标返回对应页的本地 `/markets`；记录收到的游标（已解码）
+    async fn markets_server(pages: HashMap<&'static str, Value>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let cursors = Arc::new(Mutex::new(Vec::new()));
+        let log = cursors.clone();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let (rd, mut wr) = sock.into_split();
+                let mut rd = BufReader::new(rd);
+                let (pages, log) = (pages.clone(), log.clone());
+                tokio::spawn(async move {
+                    loop {
+                        let mut request = String::new();
+                        if rd.read_line(&mut request).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let mut line = String::new();
+                        while rd.read_line(&mut line).await.unwrap_or(0) > 2 {
+                            line.clear();
+                        }
+                        let target = request.split(' ').nth(1).unwrap_or_default();
+                        let cursor = target.split_once("next_cursor=").map_or("", |(_, c)| c).replace("%3D", "=");
+                        let body = pages.get(cursor.as_str()).cloned().unwrap_or_else(|| json!({ "data": [] })).to_string();
+                        log.lock().unwrap().push(cursor);
+                        let resp = format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+                        if wr.write_all(resp.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (base, cursors)
+    }
+
+    fn market(id: &str, tokens: &[&str], extra: Value) -> Value {
+        let mut m = json!({
+            "condition_id": id,
+            "active": true,
+            "closed": false,
+            "tokens": tokens.iter().map(|t| json!({ "token_id": t, "outcome": "Yes" })).collect::<Vec<_>>(),
+        });
+        m.as_object_mut().unwrap().extend(extra.as_object().cloned().unwrap_or_default());
+        m
+    }
+
+    fn page(next: &str, data: Vec<Value>) -> Value {
+        json!({ "next_cursor": next, "data": data })
+    }
+
+    /// 本地 `/books`：第 n 个请求按 `script[n]` 的状态与 `Retry-After` 响应，超出后重复最后一项；
+    /// 200 返回空数组。返回收到的请求数
+    async fn books_server(script: Vec<(u16, Option<&'static str>)>) -> (String, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let count = Arc::new(Mutex::new(0));
+        let log = count.clone();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let (rd, mut wr) = sock.into_split();
+                let mut rd = BufReader::new(rd);
+                let (script, log) = (script.clone(), log.clone());
+                tokio::spawn(async move {
+                    loop {
+                        let mut len = 0;
+                        let mut line = String::new();
+                        if rd.read_line(&mut line).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        loop {
+                            line.clear();
+                            if rd.read_line(&mut line).await.unwrap_or(0) <= 2 {
+                                break;
+                            }
+                            if let Some((k, v)) = line.split_once(':') {
+                                if k.eq_ignore_ascii_case("content-length") {
+                                    len = v.trim().parse().unwrap();
+                                }
+                            }
+                        }
+                        let mut body = vec![0; len];
+                        rd.read_exact(&mut body).await.unwrap();
+                        let n = {
+                            let mut c = log.lock().unwrap();
+                            *c += 1;
+                            *c
+                        };
+                        let (status, retry_after) = script[(n - 1).min(script.len() - 1)];
+                        let body = if status == 200 { "[]" } else { "busy" };
+                        let header = retry_after.map(|v| format!("retry-after: {}\r\n", v)).unwrap_or_default();
+                        let resp = format!("HTTP/1.1 {} X\r\n{}content-length: {}\r\n\r\n{}", status, header, body.len(), body);
+                        if wr.write_all(resp.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (base, count)
+    }
+
+    fn policy(max_attempts: u32, base_backoff_ms: u64, max_backoff_ms: u64) -> RetryPolicy {
+        RetryPolicy { max_attempts, base_backoff_ms, max_backoff_ms, jitter: 0.0, ..RetryPolicy::default() }
+    }
+
+    fn far() -> Instant {
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_cap_and_jitter_stays_in_bounds() {
+        let p = policy(10, 100, 1000);
+        let ms: Vec<u128> = (1..=6).map(|a| backoff(&p, a).as_millis()).collect();
+        assert_eq!(ms, [100, 200, 400, 800, 1000, 1000]);
+        let p = RetryPolicy { jitter: 0.5, ..p };
+        for attempt in 1..=6 {
+            let exp = (100u64 << (attempt - 1)).min(1000);
+            for _ in 0..200 {
+                let d = backoff(&p, attempt).as_millis() as u64;
+                assert!((exp / 2..=exp).contains(&d), "attempt {} waited {}ms", attempt, d);
+            }
+        }
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        let headers = |v: &str| HeaderMap::from_iter([(RETRY_AFTER, v.parse().unwrap())]);
+        assert_eq!(parse_retry_after(&headers(" 3 ")), Some(Duration::from_secs(3)));
+        let at = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let d = parse_retry_after(&headers(&at)).unwrap();
+        assert!(d > Duration::from_secs(28) && d <= Duration::from_secs(30), "{:?}", d);
+        assert_eq!(parse_retry_after(&headers("Mon, 01 Jan 2001 00:00:00 +0000")), None);
+        assert_eq!(parse_retry_after(&headers("soon")), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn retries_429_and_5xx_until_success() {
+        let (base, count) = books_server(vec![(429, None), (503, None), (200, None)]).await;
+        let http = HttpClient::new(base).unwrap().with_retry(policy(5, 1, 10));
+        let mut permits = 0;
+        let permit = || {
+            permits += 1;
+            true
+        };
+        let books = http.get_books_with(&["t1".into()], far(), permit).await.unwrap();
+        assert!(books.is_empty());
+        assert_eq!((*count.lock().unwrap(), permits), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn other_4xx_is_not_retried_and_attempts_are_bounded() {
+        let (base, count) = books_server(vec![(404, None)]).await;
+        let http = HttpClient::new(base).unwrap().with_retry(policy(5, 1, 10));
+        let err = http.get_books_with(&["t1".into()], far(), || true).await.unwrap_err();
+        assert!(matches!(err, PolyObError::ClientStatus(ref s) if s.status == 404), "{:?}", err);
+        assert_eq!(*count.lock().unwrap(), 1);
+
+        let (base, count) = books_server(vec![(500, None)]).await;
+        let http = HttpClient::new(base).unwrap().with_retry(policy(3, 1, 10));
+        let err = http.get_books_with(&["t1".into()], far(), || true).await.unwrap_err();
+        assert!(matches!(err, PolyObError::ServerStatus(ref s) if s.status == 500), "{:?}", err);
+        assert_eq!(*count.lock().unwrap(), 3);
+
+        // 取不到重试额度即返回本次错误
+        let (base, count) = books_server(vec![(503, None)]).await;
+        let http = HttpClient::new(base).unwrap().with_retry(policy(3, 1, 10));
+        let err = http.get_books_with(&["t1".into()], far(), || false).await.unwrap_err();
+        assert_eq!((err.status(), *count.lock().unwrap()), (Some(503), 1));
+    }
+
+    #[tokio::test]
+    async fn retry_after_overrides_backoff_and_is_capped() {
+        // 退避 1ms，但 Retry-After 为 1s，按上限 100ms 等待
+        let (base, count) = books_server(vec![(429, Some("1")), (200, None)]).await;
+        let http = HttpClient::new(base).unwrap().with_retry(policy(3, 1, 100));
+        let start = Instant::now();
+        http.get_books_with(&["t1".into()], far(), || true).await.unwrap();
+        let took = start.elapsed();
+        assert!(took >= Duration::from_millis(100) && took < Duration::from_millis(900), "{:?}", took);
+        assert_eq!(*count.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_when_next_attempt_would_miss_the_deadline() {
+        // 退避依次为 100ms、200ms：第二次失败后再等 200ms 会越过 250ms 的时限
+        let (base, count) = books_server(vec![(503, None)]).await;
+        let http = HttpClient::new(base).unwrap().with_retry(policy(10, 100, 1000));
+        let start = Instant::now();
+        let err = http.get_books_with(&["t1".into()], start + Duration::from_millis(250), || true).await.unwrap_err();
+        assert!(start.elapsed() < Duration::from_millis(250));
+        match err {
+            PolyObError::DeadlineExceeded { attempts, ref last } => {
+                assert_eq!((attempts, last.status()), (2, Some(503)));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(*count.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn discover_follows_cursor_until_end_marker() {
+        let pages = HashMap::from([
+            ("", page("MA==", vec![market("c1", &["b", "a"], json!({})), market("c2", &["x"], json!({ "active": false }))])),
+            ("MA==", page("MTAw", vec![market("c3", &["c", ""], json!({})), market("c4", &["y"], json!({ "closed": true }))])),
+            ("MTAw", page(MARKETS_END_CURSOR, vec![market("c5", &["a", "d"], json!({ "enable_order_book": false })), market("c6", &["e"], json!({}))])),
+            (MARKETS_END_CURSOR, page(MARKETS_END_CURSOR, vec![market("c7", &["never"], json!({}))])),
+        ]);
+        let (base, cursors) = markets_server(pages).await;
+        let http = HttpClient::new(base).unwrap();
+        let tokens = http.discover_tokens(&MarketFilter::default()).await.unwrap();
+        assert_eq!(tokens, ["a", "b", "c", "e"]);
+        assert_eq!(*cursors.lock().unwrap(), ["", "MA==", "MTAw"]);
+
+        let filter = MarketFilter { max_pages: Some(2), ..MarketFilter::default() };
+        cursors.lock().unwrap().clear();
+        assert_eq!(http.discover_tokens(&filter).await.unwrap(), ["a", "b", "c"]);
+        assert_eq!(*cursors.lock().unwrap(), ["", "MA=="]);
+    }
+
+    #[tokio::test]
+    async fn discover_stops_on_non_advancing_or_empty_cursor() {
+        let pages = HashMap::from([
+            ("", page("AAA", vec![market("c1", &["a"], json!({}))])),
+            ("AAA", page("AAA", vec![market("c2", &["b"], json!({}))])),
+        ]);
+        let (base, cursors) = markets_server(pages).await;
+        let tokens = HttpClient::new(base).unwrap().discover_tokens(&MarketFilter::default()).await.unwrap();
+        assert_eq!(tokens, ["a", "b"]);
+        assert_eq!(*cursors.lock().unwrap(), ["", "AAA"]);
+
+        let pages = HashMap::from([("", json!({ "data": [market("c1", &["a"], json!({}))] }))]);
+        let (base, cursors) = markets_server(pages).await;
+        let tokens = HttpClient::new(base).unwrap().discover_tokens(&MarketFilter::default()).await.unwrap();
+        assert_eq!(tokens, ["a"]);
+        assert_eq!(cursors.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn discover_applies_filters() {
+        let pages = HashMap::from([(
+            "",
+            page(
+                MARKETS_END_CURSOR,
+                vec![
+                    market("c1", &["politics"], json!({ "tags": ["Politics"], "volume": "25000", "end_date_iso": "2025-06-01T00:00:00Z" })),
+                    market("c2", &["sports"], json!({ "tags": ["Sports"], "volume": 50000, "end_date_iso": "2025-06-01" })),
+                    market("c3", &["thin"], json!({ "tags": ["politics"], "volume": 10, "end_date_iso": "2025-06-01T00:00:00Z" })),
+                    market("c4", &["late"], json!({ "tags": ["Politics"], "volume": 99999, "end_date_iso": "2027-01-01T00:00:00Z" })),
+                    market("c5", &["undated"], json!({ "tags": ["Politics"], "volume": 99999 })),
+                    market("c6", &["closed"], json!({ "tags": ["Politics"], "volume": 99999, "closed": true, "end_date_iso": "2025-06-01" })),
+                ],
+            ),
+        )]);
+        let (base, _) = markets_server(pages).await;
+        let http = HttpClient::new(base).unwrap();
+        let filter = MarketFilter {
+            tags: vec!["POLITICS".into()],
+            min_volume: Some(1000.0),
+            end_after: Some("2025-01-01T00:00:00Z".parse().unwrap()),
+            end_before: Some("2026-01-01T00:00:00Z".parse().unwrap()),
+            ..MarketFilter::default()
+        };
+        assert_eq!(http.discover_tokens(&filter).await.unwrap(), ["politics"]);
+        let filter = MarketFilter { include_closed: true, ..filter };
+        assert_eq!(http.discover_tokens(&filter).await.unwrap(), ["closed", "politics"]);
+        let filter = MarketFilter { active_only: false, include_closed: false, ..MarketFilter::default() };
+        assert_eq!(http.discover_tokens(&filter).await.unwrap(), ["late", "politics", "sports", "thin", "undated"]);
+    }
+}
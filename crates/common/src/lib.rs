@@ -1,6 +1,11 @@
+pub mod analytics;
+pub mod book;
+pub mod decimal;
+pub mod error;
 pub mod types;
 pub mod redisx;
 pub mod http;
 pub mod lua;
 pub mod settings;
+pub mod protocol;
 
@@ -1,10 +1,16 @@
 pub const LUA_CAS_UPDATE: &str = r#"
 -- KEYS[1]=ob:{token_id}
--- ARGV: new_hash, new_ts, bids_json, asks_json, now_ms, market
+-- ARGV: new_hash, new_ts, bids_json, asks_json, now_ms, market, force, [field, value]...
+-- force=1：对账修复，hash 相同也覆盖（仍不允许时间戳回退）
+-- 其后成对的派生指标随快照一并写入，值为空串时删除该字段
 local h = redis.call('HGETALL', KEYS[1])
 local cur = {}
 for i=1,#h,2 do cur[h[i]] = h[i+1] end
-if cur['hash'] == ARGV[1] then return 'skip_hash' end
+if cur['hash'] == ARGV[1] and ARGV[7] ~= '1' then
+  -- 内容未变：仅记录确认时刻，供陈旧检测区分“安静”与“未刷新”
+  redis.call('HSET', KEYS[1], 'checked_at', ARGV[5])
+  return 'skip_hash'
+end
 local cur_ts = tonumber(cur['timestamp'] or '0')
 local new_ts = tonumber(ARGV[2])
 if new_ts < cur_ts then return 'skip_ts' end
@@ -12,7 +18,41 @@ redis.call('HMSET', KEYS[1],
   'hash', ARGV[1], 'timestamp', ARGV[2],
   'bids', ARGV[3], 'asks', ARGV[4], 'updated_at', ARGV[5], 'market', ARGV[6]
 )
+for i=8,#ARGV-1,2 do
+  if ARGV[i+1] == '' then
+    redis.call('HDEL', KEYS[1], ARGV[i])
+  else
+    redis.call('HSET', KEYS[1], ARGV[i], ARGV[i+1])
+  end
+end
 return 'updated'
 "#;
 
 
+
+pub const LUA_LEASE_ACQUIRE: &str = r#"
+-- KEYS[1]=lease key, KEYS[2]=fence counter
+-- ARGV: holder, ttl_ms
+-- 抢占成功返回新的 fencing token（单调递增），否则返回 nil
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+  return redis.call('INCR', KEYS[2])
+end
+return false
+"#;
+
+pub const LUA_LEASE_RENEW: &str = r#"
+-- KEYS[1]=lease key; ARGV: holder, ttl_ms
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  redis.call('PEXPIRE', KEYS[1], ARGV[2])
+  return 1
+end
+return 0
+"#;
+
+pub const LUA_LEASE_RELEASE: &str = r#"
+-- KEYS[1]=lease key; ARGV: holder
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
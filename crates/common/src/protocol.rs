@@ -0,0 +1,199 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::types::QuarantinedToken;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// 单帧上限，防止异常长度前缀导致超大分配
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Client → Fetch 指令，`id` 由 Client 分配，Fetch 在 Ack 中原样带回
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub id: u64,
+    /// 发送方 leader 的 fencing token；未启用选主时缺省
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fence: Option<u64>,
+    /// 抓取时限（毫秒，自节点收到指令起算）：节点以此作为上游请求的总超时，超时即放弃并回 `deadline_exceeded`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    #[serde(flatten)]
+    pub cmd: Command,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    /// 设置 payload 并抓取；`tokens` 为空时等同 `Replay`
+    #[serde(alias = "fetch")]
+    Set {
+        #[serde(default)]
+        tokens: Vec<String>,
+    },
+    /// 按上一次 payload 抓取
+    Replay,
+    /// 追加到上一次 payload（去重）后抓取
+    Append { tokens: Vec<String> },
+    /// 清空上一次 payload，不触发抓取
+    Clear,
+    /// 握手：节点在 Ack 中返回自身信息（node_id、容量）
+    Hello,
+    /// 存活检查：只回 Ack，不做任何 I/O
+    Ping,
+    /// 应用层健康：Ack 的 `report` 携带节点运行状态
+    Status,
+}
+
+impl Command {
+    /// 是否会触发一次 `/books` 请求（受节点限速约束）
+    pub fn triggers_fetch(&self) -> bool {
+        matches!(self, Command::Set { .. } | Command::Replay | Command::Append { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckStatus {
+    Ok,
+    Error,
+    /// 超出节点 `capacity_rps`，本次未执行
+    RateLimited,
+    /// 指令携带的 fencing token 低于节点已见过的最大值，来自已失去租约的 leader
+    StaleFence,
+    /// 未能在指令携带的时限内完成，未完成的上游请求已取消
+    DeadlineExceeded,
+}
+
+/// 节点自述信息，随 `hello` 的 Ack 返回
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub node_id: String,
+    pub capacity_rps: u32,
+}
+
+/// 最近一次上游 `/books` 请求的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamState {
+    /// HTTP 状态码；连接失败、超时等未拿到响应时为 `None`
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub at_ms: i64,
+}
+
+/// 节点运行状态，随 `status` 的 Ack 返回
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub node_id: String,
+    pub capacity_rps: u32,
+    pub uptime_ms: u64,
+    pub redis_ok: bool,
+    pub last_upstream: Option<UpstreamState>,
+    pub in_flight: usize,
+    /// 最近 1 秒实际发出的 `/books` 请求数
+    pub current_rps: u32,
+    /// `current_rps / capacity_rps`
+    pub rate_usage: f64,
+}
+
+/// Fetch → Client 回执，同一连接上可乱序返回，按 `id` 关联
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ack {
+    pub id: u64,
+    pub status: AckStatus,
+    #[serde(default)]
+    pub fetched: usize,
+    #[serde(default)]
+    pub updated: usize,
+    /// 本批中订单簿发生变化（CAS 写入）的 token；其余已抓取的 token 视为未变化
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<String>,
+    /// 本批对分定位出并已写入隔离表的 token
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quarantined: Vec<QuarantinedToken>,
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 上游 `/books` 的非 2xx 状态码
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<NodeInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<NodeStatus>,
+}
+
+impl Ack {
+    pub fn ok(id: u64) -> Self {
+        Self {
+            id,
+            status: AckStatus::Ok,
+            fetched: 0,
+            updated: 0,
+            changed: Vec::new(),
+            quarantined: Vec::new(),
+            latency_ms: 0,
+            error: None,
+            http_status: None,
+            node: None,
+            report: None,
+        }
+    }
+
+    pub fn error(id: u64, err: impl ToString) -> Self {
+        Self { error: Some(err.to_string()), status: AckStatus::Error, ..Self::ok(id) }
+    }
+
+    pub fn rate_limited(id: u64) -> Self {
+        Self { status: AckStatus::RateLimited, ..Self::ok(id) }
+    }
+
+    pub fn deadline_exceeded(id: u64, deadline_ms: u64) -> Self {
+        Self { status: AckStatus::DeadlineExceeded, error: Some(format!("deadline of {}ms exceeded", deadline_ms)), ..Self::ok(id) }
+    }
+
+    pub fn stale_fence(id: u64, seen: u64) -> Self {
+        Self { status: AckStatus::StaleFence, error: Some(format!("stale fence, node has seen {}", seen)), ..Self::ok(id) }
+    }
+}
+
+/// 写一帧：4 字节大端长度前缀 + JSON
+pub async fn write_frame<W, T>(w: &mut W, msg: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let data = serde_json::to_vec(msg)?;
+    if data.len() > MAX_FRAME_LEN {
+        anyhow::bail!("frame too large: {} bytes", data.len());
+    }
+    let mut buf = Vec::with_capacity(4 + data.len());
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&data);
+    w.write_all(&buf).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+/// 读一帧；在帧边界处遇到 EOF 返回 `None`
+pub async fn read_frame<R, T>(r: &mut R) -> Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut len_buf = [0u8; 4];
+    if let Err(e) = r.read_exact(&mut len_buf).await {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(e.into());
+    }
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_LEN {
+        anyhow::bail!("frame too large: {} bytes", len);
+    }
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf).await?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
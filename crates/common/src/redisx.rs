@@ -1,7 +1,28 @@
-use anyhow::Result;
 use redis::AsyncCommands;
-use crate::lua::LUA_CAS_UPDATE;
-use crate::types::{OrderBookSnapshot, RedisBookRecord};
+use crate::analytics::BookStats;
+use crate::error::{PolyObError, Result};
+use crate::lua::{LUA_CAS_UPDATE, LUA_LEASE_ACQUIRE, LUA_LEASE_RELEASE, LUA_LEASE_RENEW};
+use crate::types::{NodeRecord, OrderBookSnapshot, QuarantinedToken, RedisBookRecord};
+use serde::Serialize;
+
+pub const NODES_SET: &str = "nodes";
+/// 分歧计数：token_id → 累计次数
+pub const DIVERGENCE_HASH: &str = "divergence";
+/// 隔离表：token_id → `QuarantinedToken` JSON
+pub const QUARANTINE_HASH: &str = "quarantine";
+
+type BookFields = (Option<String>, Option<String>, Option<String>, Option<String>, Option<i64>, Option<String>);
+
+/// `cas_upsert_book` 的写入结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CasOutcome {
+    /// 写入了新快照
+    Updated,
+    /// hash 未变，仅刷新 `checked_at`
+    SkipHash,
+    /// 快照时间戳早于已存版本，丢弃
+    SkipTs,
+}
 
 #[derive(Clone)]
 pub struct RedisClient {
@@ -15,26 +36,50 @@ impl RedisClient {
         Ok(Self { conn })
     }
 
-    pub async fn cas_upsert_book(&mut self, ob: &OrderBookSnapshot, now_ms: i64) -> Result<String> {
+    pub async fn ping(&mut self) -> Result<()> {
+        let _: String = redis::cmd("PING").query_async(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// CAS 写入快照（及派生指标）：hash 未变只刷新 `checked_at`，时间戳回退则丢弃
+    pub async fn cas_upsert_book(&mut self, ob: &OrderBookSnapshot, now_ms: i64, stats: Option<&BookStats>) -> Result<CasOutcome> {
+        self.upsert_book(ob, now_ms, false, stats).await
+    }
+
+    /// 以权威快照修复已存副本：hash 相同也覆盖，时间戳回退仍丢弃
+    pub async fn repair_book(&mut self, ob: &OrderBookSnapshot, now_ms: i64, stats: Option<&BookStats>) -> Result<CasOutcome> {
+        self.upsert_book(ob, now_ms, true, stats).await
+    }
+
+    async fn upsert_book(&mut self, ob: &OrderBookSnapshot, now_ms: i64, force: bool, stats: Option<&BookStats>) -> Result<CasOutcome> {
         let key = format!("ob:{}", ob.asset_id);
         let bids = serde_json::to_string(&ob.bids)?;
         let asks = serde_json::to_string(&ob.asks)?;
-        let rv: String = redis::Script::new(LUA_CAS_UPDATE)
-            .key(key)
+        let script = redis::Script::new(LUA_CAS_UPDATE);
+        let mut invocation = script.key(key);
+        invocation
             .arg(&ob.hash)
             .arg(&ob.timestamp)
             .arg(bids)
             .arg(asks)
             .arg(now_ms)
             .arg(&ob.market)
-            .invoke_async(&mut self.conn)
-            .await?;
-        Ok(rv)
+            .arg(if force { "1" } else { "0" });
+        for (field, value) in stats.map(BookStats::fields).unwrap_or_default() {
+            invocation.arg(field).arg(value);
+        }
+        let rv: String = invocation.invoke_async(&mut self.conn).await?;
+        match rv.as_str() {
+            "updated" => Ok(CasOutcome::Updated),
+            "skip_hash" => Ok(CasOutcome::SkipHash),
+            "skip_ts" => Ok(CasOutcome::SkipTs),
+            _ => Err(PolyObError::Script { script: "cas_update", outcome: rv }),
+        }
     }
 
     pub async fn get_book(&mut self, token_id: &str) -> Result<Option<RedisBookRecord>> {
         let key = format!("ob:{}", token_id);
-        let (bids, asks, hash, timestamp, updated_at, market): (Option<String>, Option<String>, Option<String>, Option<String>, Option<i64>, Option<String>) = self
+        let (bids, asks, hash, timestamp, updated_at, market): BookFields = self
             .conn
             .hget(&key, ("bids", "asks", "hash", "timestamp", "updated_at", "market"))
             .await?;
@@ -47,6 +92,148 @@ impl RedisClient {
         }
     }
 
+    /// 各 token 快照最近一次被确认新鲜的时刻（毫秒）：`updated_at` 与 `checked_at` 取大；无快照为 `None`
+    pub async fn freshness(&mut self, token_ids: &[String]) -> Result<Vec<Option<i64>>> {
+        let mut out = Vec::with_capacity(token_ids.len());
+        for chunk in token_ids.chunks(500) {
+            let mut pipe = redis::pipe();
+            for t in chunk {
+                pipe.cmd("HMGET").arg(format!("ob:{}", t)).arg("updated_at").arg("checked_at");
+            }
+            // 每行按数组解析：以元组接收时 redis-rs 会把外层结果当作扁平的成对列表
+            let rows: Vec<Vec<Option<i64>>> = pipe.query_async(&mut self.conn).await?;
+            out.extend(rows.into_iter().map(|row| row.into_iter().flatten().max()));
+        }
+        Ok(out)
+    }
+
+    /// 写入/续期节点注册；TTL 到期未续即视为下线
+    pub async fn register_node(&mut self, rec: &NodeRecord, ttl_ms: u64) -> Result<()> {
+        let key = format!("node:{}", rec.node_id);
+        let val = serde_json::to_string(rec)?;
+        redis::pipe()
+            .cmd("SET").arg(&key).arg(val).arg("PX").arg(ttl_ms).ignore()
+            .cmd("SADD").arg(NODES_SET).arg(&rec.node_id).ignore()
+            .query_async::<_, ()>(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn unregister_node(&mut self, node_id: &str) -> Result<()> {
+        redis::pipe()
+            .cmd("DEL").arg(format!("node:{}", node_id)).ignore()
+            .cmd("SREM").arg(NODES_SET).arg(node_id).ignore()
+            .query_async::<_, ()>(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// 读取仍在 TTL 内的节点；已过期的 id 顺带从索引集合中移除
+    pub async fn live_nodes(&mut self) -> Result<Vec<NodeRecord>> {
+        let ids: Vec<String> = self.conn.smembers(NODES_SET).await?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = ids.iter().map(|id| format!("node:{}", id)).collect();
+        let vals: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query_async(&mut self.conn).await?;
+        let mut out = Vec::with_capacity(ids.len());
+        for (id, val) in ids.iter().zip(vals) {
+            match val.map(|v| serde_json::from_str::<NodeRecord>(&v)) {
+                Some(Ok(rec)) => out.push(rec),
+                Some(Err(e)) => tracing::warn!(node_id = %id, "bad node record: {}", e),
+                None => {
+                    let _: i64 = self.conn.srem(NODES_SET, id).await?;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// 读取 Redis 集合形式维护的 token 列表，按字典序返回保证切分稳定
+    pub async fn token_set(&mut self, key: &str) -> Result<Vec<String>> {
+        let mut tokens: Vec<String> = self.conn.smembers(key).await?;
+        tokens.sort();
+        Ok(tokens)
+    }
+
+    /// 尝试获取租约（SET NX PX）；成功时返回新的 fencing token
+    pub async fn acquire_lease(&mut self, key: &str, holder: &str, ttl_ms: u64) -> Result<Option<u64>> {
+        let fence: Option<u64> = redis::Script::new(LUA_LEASE_ACQUIRE)
+            .key(key)
+            .key(format!("{}:fence", key))
+            .arg(holder)
+            .arg(ttl_ms)
+            .invoke_async(&mut self.conn)
+            .await?;
+        Ok(fence)
+    }
+
+    /// 仅当租约仍属于 `holder` 时续期
+    pub async fn renew_lease(&mut self, key: &str, holder: &str, ttl_ms: u64) -> Result<bool> {
+        let rv: i64 = redis::Script::new(LUA_LEASE_RENEW)
+            .key(key)
+            .arg(holder)
+            .arg(ttl_ms)
+            .invoke_async(&mut self.conn)
+            .await?;
+        Ok(rv == 1)
+    }
+
+    pub async fn release_lease(&mut self, key: &str, holder: &str) -> Result<()> {
+        let _: i64 = redis::Script::new(LUA_LEASE_RELEASE)
+            .key(key)
+            .arg(holder)
+            .invoke_async(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn quarantine(&mut self, tokens: &[QuarantinedToken]) -> Result<()> {
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let mut fields = Vec::with_capacity(tokens.len());
+        for t in tokens {
+            fields.push((t.token_id.as_str(), serde_json::to_string(t)?));
+        }
+        let _: () = self.conn.hset_multiple(QUARANTINE_HASH, &fields).await?;
+        Ok(())
+    }
+
+    /// 读取隔离表；无法解析的记录跳过
+    pub async fn quarantined(&mut self) -> Result<Vec<QuarantinedToken>> {
+        let vals: Vec<String> = self.conn.hvals(QUARANTINE_HASH).await?;
+        Ok(vals
+            .iter()
+            .filter_map(|v| match serde_json::from_str(v) {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    tracing::warn!("bad quarantine record: {}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    pub async fn release_quarantine(&mut self, token_ids: &[String]) -> Result<()> {
+        if token_ids.is_empty() {
+            return Ok(());
+        }
+        let _: i64 = self.conn.hdel(QUARANTINE_HASH, token_ids).await?;
+        Ok(())
+    }
+
+    /// 累加 token 的分歧计数，返回累加后的值
+    pub async fn count_divergence(&mut self, token_id: &str) -> Result<u64> {
+        Ok(self.conn.hincr(DIVERGENCE_HASH, token_id, 1).await?)
+    }
+
+    pub async fn publish_json<T: Serialize>(&mut self, channel: &str, msg: &T) -> Result<()> {
+        let msg = serde_json::to_string(msg)?;
+        let _: i64 = self.conn.publish(channel, msg).await?;
+        Ok(())
+    }
+
     pub async fn publish_update(&mut self, channel: &str, ob: &OrderBookSnapshot) -> Result<()> {
         let msg = serde_json::to_string(ob)?;
         let _: i64 = redis::cmd("PUBLISH")
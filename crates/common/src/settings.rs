@@ -1,14 +1,311 @@
-use anyhow::Result;
+use crate::decimal::Decimal;
+use crate::error::{PolyObError, Result};
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ClientConfig {
     pub redis_url: String,
     pub base_url: String,
+    #[serde(default)]
     pub tokens: Vec<String>,
+    #[serde(default)]
     pub fetch_nodes: Vec<String>, // ip:port (tcp socket)
+    /// 从 Redis 注册表发现存活节点（与 `fetch_nodes` 合并）
+    #[serde(default)]
+    pub node_discovery: bool,
     #[serde(default = "default_plan_horizon")] 
     pub plan_horizon_secs: u64,
+    /// 每片抓取指令携带的时限，节点超时即放弃；Client 等 Ack 的时长为该值加 500ms
+    #[serde(default = "default_slice_deadline")]
+    pub slice_deadline_ms: u64,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub leader: LeaderConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub markets: MarketDiscoveryConfig,
+    /// 刷新档位，按书写顺序即优先级
+    #[serde(default)]
+    pub tiers: Vec<TierConfig>,
+    /// 未归入任何档位的 token 的目标刷新间隔；缺省时尽力而为，分享剩余预算
+    #[serde(default)]
+    pub default_refresh_ms: Option<u64>,
+    #[serde(default)]
+    pub change_bias: ChangeBiasConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub quarantine: QuarantineConfig,
+}
+
+/// Fetch 对分定位出的坏 token：从计划中剔除，到期后放回计划复检
+#[derive(Debug, Deserialize, Clone)]
+pub struct QuarantineConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 同步 Redis 隔离表的间隔
+    #[serde(default = "default_quarantine_sync")]
+    pub sync_ms: u64,
+    /// 隔离满该时长后解除，重新纳入计划；仍然 4xx 时由 Fetch 再次隔离
+    #[serde(default = "default_revalidate")]
+    pub revalidate_secs: u64,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        Self { enabled: true, sync_ms: default_quarantine_sync(), revalidate_secs: default_revalidate() }
+    }
+}
+
+/// 快照陈旧巡检
+#[derive(Debug, Deserialize, Clone)]
+pub struct WatchdogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_watchdog_interval")]
+    pub interval_ms: u64,
+    /// 超过该时长未确认新鲜即视为陈旧
+    #[serde(default = "default_stale_ms")]
+    pub stale_ms: u64,
+    /// 告警发布频道
+    #[serde(default = "default_stale_channel")]
+    pub channel: String,
+    /// 单次派发最多额外携带的陈旧 token 数
+    #[serde(default = "default_boost")]
+    pub boost: usize,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: default_watchdog_interval(),
+            stale_ms: default_stale_ms(),
+            channel: default_stale_channel(),
+            boost: default_boost(),
+        }
+    }
+}
+
+/// 按变化频率分配剩余预算：常变的 token 刷新更勤，安静的 token 保底刷新
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChangeBiasConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 尽力而为 token 的保底刷新间隔
+    #[serde(default = "default_floor_refresh")]
+    pub floor_refresh_ms: u64,
+    /// 变化率的指数平滑系数（每次观测的权重）
+    #[serde(default = "default_change_alpha")]
+    pub alpha: f64,
+    /// 按最新变化率重建计划的最小间隔（同时不短于一个完整周期）
+    #[serde(default = "default_bias_replan")]
+    pub replan_secs: u64,
+}
+
+impl Default for ChangeBiasConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            floor_refresh_ms: default_floor_refresh(),
+            alpha: default_change_alpha(),
+            replan_secs: default_bias_replan(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// 需要追踪的 tokens：`tokens` 在前，其后是各档位中列出的 token，去重
+    pub fn tracked_tokens(&self) -> Vec<String> {
+        let mut seen = std::collections::HashSet::new();
+        self.tokens
+            .iter()
+            .chain(self.tiers.iter().flat_map(|t| &t.tokens))
+            .filter(|t| seen.insert(t.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// 刷新档位：档内 token 的目标刷新间隔；预算不足时靠前的档位优先满足
+#[derive(Debug, Deserialize, Clone)]
+pub struct TierConfig {
+    pub name: String,
+    pub refresh_ms: u64,
+    #[serde(default)]
+    pub tokens: Vec<String>,
+}
+
+/// 从 CLOB `/markets` 自动发现需要追踪的 tokens（与配置文件 tokens 合并）
+#[derive(Debug, Deserialize, Clone)]
+pub struct MarketDiscoveryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 重新拉取市场列表的间隔
+    #[serde(default = "default_market_interval")]
+    pub interval_secs: u64,
+    #[serde(flatten)]
+    pub filter: MarketFilter,
+}
+
+impl Default for MarketDiscoveryConfig {
+    fn default() -> Self {
+        Self { enabled: false, interval_secs: default_market_interval(), filter: MarketFilter::default() }
+    }
+}
+
+/// 市场过滤条件，全部满足才纳入
+#[derive(Debug, Deserialize, Clone)]
+pub struct MarketFilter {
+    /// 只要 `active` 的市场
+    #[serde(default = "default_true")]
+    pub active_only: bool,
+    /// 是否纳入已 `closed` 的市场
+    #[serde(default)]
+    pub include_closed: bool,
+    /// 非空时市场须带有其中任一标签（不区分大小写）
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 结束时间不早于该时刻（RFC 3339）
+    #[serde(default)]
+    pub end_after: Option<DateTime<Utc>>,
+    /// 结束时间不晚于该时刻（RFC 3339）
+    #[serde(default)]
+    pub end_before: Option<DateTime<Utc>>,
+    /// 最小成交量；接口未返回成交量的市场按 0 计
+    #[serde(default)]
+    pub min_volume: Option<f64>,
+    /// 最多翻页数；缺省翻到末页
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+}
+
+impl Default for MarketFilter {
+    fn default() -> Self {
+        Self {
+            active_only: true,
+            include_closed: false,
+            tags: Vec::new(),
+            end_after: None,
+            end_before: None,
+            min_volume: None,
+            max_pages: None,
+        }
+    }
+}
+
+/// 运行中热加载 tokens 与节点列表
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReloadConfig {
+    /// 监视配置文件，`tokens` / `fetch_nodes` 变化时在线生效
+    #[serde(default = "default_true")]
+    pub watch_config: bool,
+    /// 额外从该 Redis 集合读取 tokens（与配置文件中的 tokens 合并）
+    #[serde(default)]
+    pub token_set: Option<String>,
+    #[serde(default = "default_reload_interval")]
+    pub interval_ms: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self { watch_config: true, token_set: None, interval_ms: default_reload_interval() }
+    }
+}
+
+/// 多 Client 热备：经 Redis 租约选主，仅 leader 调度
+#[derive(Debug, Deserialize, Clone)]
+pub struct LeaderConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_leader_key")]
+    pub key: String,
+    /// 租约 TTL；leader 失联后备机最迟约在该时长后接管
+    #[serde(default = "default_lease_ttl")]
+    pub ttl_ms: u64,
+}
+
+impl Default for LeaderConfig {
+    fn default() -> Self {
+        Self { enabled: false, key: default_leader_key(), ttl_ms: default_lease_ttl() }
+    }
+}
+
+/// 自适应批大小（AIMD）参数
+#[derive(Debug, Deserialize, Clone)]
+pub struct BatchConfig {
+    #[serde(default = "default_batch_min")]
+    pub min: usize,
+    #[serde(default = "default_batch_max")]
+    pub max: usize,
+    /// 初始批大小；缺省时按“每 token 约每秒刷新一次”计算
+    #[serde(default)]
+    pub initial: Option<usize>,
+    /// 加性增：每次低延迟成功后增加的 token 数
+    #[serde(default = "default_batch_increase")]
+    pub increase: usize,
+    /// 乘性减：超时/5xx/429/高延迟时乘以该系数
+    #[serde(default = "default_batch_decrease")]
+    pub decrease: f64,
+    /// Ack 延迟超过该值视为过载
+    #[serde(default = "default_target_latency")]
+    pub target_latency_ms: u64,
+    /// 413 压低上限后，连续这么多次低延迟成功即把上限恢复到 `max`
+    #[serde(default = "default_ceiling_recover_after")]
+    pub ceiling_recover_after: u32,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            min: default_batch_min(),
+            max: default_batch_max(),
+            initial: None,
+            increase: default_batch_increase(),
+            decrease: default_batch_decrease(),
+            target_latency_ms: default_target_latency(),
+            ceiling_recover_after: default_ceiling_recover_after(),
+        }
+    }
+}
+
+/// 节点健康判定与故障转移参数
+#[derive(Debug, Deserialize, Clone)]
+pub struct HealthConfig {
+    #[serde(default = "default_probe_interval")]
+    pub probe_interval_ms: u64,
+    #[serde(default = "default_probe_timeout")]
+    pub probe_timeout_ms: u64,
+    /// 连续探测失败多少次后移出轮转
+    #[serde(default = "default_down_after")]
+    pub down_after: u32,
+    /// 移出后连续探测成功多少次才重新加入
+    #[serde(default = "default_up_after")]
+    pub up_after: u32,
+    /// Ack 成功率统计窗口（最近 N 个时间片）
+    #[serde(default = "default_ack_window")]
+    pub ack_window: usize,
+    /// 窗口填满后成功率低于该值即移出轮转
+    #[serde(default = "default_min_ack_success")]
+    pub min_ack_success: f64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval_ms: default_probe_interval(),
+            probe_timeout_ms: default_probe_timeout(),
+            down_after: default_down_after(),
+            up_after: default_up_after(),
+            ack_window: default_ack_window(),
+            min_ack_success: default_min_ack_success(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -20,20 +317,275 @@ pub struct FetchConfig {
     pub capacity_rps: u32, // default 20
     #[serde(default = "default_bind")] 
     pub bind_addr: String, // 0.0.0.0:3000
+    /// 注册到 Redis 的对外地址（ip:port）；缺省时不注册
+    #[serde(default)]
+    pub advertise_addr: Option<String>,
+    #[serde(default = "default_heartbeat")]
+    pub heartbeat_ms: u64,
+    #[serde(default = "default_node_ttl")]
+    pub node_ttl_ms: u64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub bisect: BisectConfig,
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+    #[serde(default)]
+    pub ws: WsConfig,
+    #[serde(default)]
+    pub analytics: AnalyticsConfig,
+}
+
+/// 写入快照时一并写入派生指标（最优价、中间价、价差、深度、失衡等）
+#[derive(Debug, Deserialize, Clone)]
+pub struct AnalyticsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// `bid_depth` / `ask_depth` 累计的档数
+    #[serde(default = "default_depth_levels")]
+    pub depth_levels: usize,
+    /// `bid_depth_band` / `ask_depth_band` 统计距中间价多少美分以内的挂单
+    #[serde(default = "default_band_cents")]
+    pub band_cents: u32,
+}
+
+impl AnalyticsConfig {
+    pub fn band(&self) -> Decimal {
+        Decimal::new(self.band_cents as i64, 2)
+    }
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self { enabled: true, depth_levels: default_depth_levels(), band_cents: default_band_cents() }
+    }
+}
+
+/// WebSocket 增量接入：订阅本节点近期被分配的 token，`book` / `price_change` 事件经同一 CAS 写入 Redis，
+/// REST 轮询照常进行作为兜底
+#[derive(Debug, Deserialize, Clone)]
+pub struct WsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_ws_url")]
+    pub url: String,
+    /// 额外固定订阅的 token
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    /// 最近该时长内出现在抓取指令中的 token 视为分配给本节点
+    #[serde(default = "default_assign_ttl")]
+    pub assign_ttl_ms: u64,
+    /// 按分配变化增减订阅的检查间隔
+    #[serde(default = "default_resubscribe")]
+    pub resubscribe_ms: u64,
+    #[serde(default = "default_ws_ping")]
+    pub ping_secs: u64,
+    /// 断线后重连的等待时长
+    #[serde(default = "default_ws_reconnect")]
+    pub reconnect_ms: u64,
+    /// 应用增量后按 REST 补全的字段重算 hash 并与推送的 hash 比对，不一致即视为缺口重新订阅
+    #[serde(default)]
+    pub verify_hash: bool,
+    #[serde(default)]
+    pub reconcile: ReconcileConfig,
+}
+
+/// REST 快照与 WS 本地簿对账
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReconcileConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// REST 快照时间戳领先 WS 超过该时长且内容不同，视为 WS 落后
+    #[serde(default = "default_reconcile_lag")]
+    pub lag_ms: i64,
+    /// 分歧事件发布频道
+    #[serde(default = "default_divergence_channel")]
+    pub channel: String,
+}
+
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self { enabled: true, lag_ms: default_reconcile_lag(), channel: default_divergence_channel() }
+    }
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: default_ws_url(),
+            tokens: Vec::new(),
+            assign_ttl_ms: default_assign_ttl(),
+            resubscribe_ms: default_resubscribe(),
+            ping_secs: default_ws_ping(),
+            reconnect_ms: default_ws_reconnect(),
+            verify_hash: false,
+            reconcile: ReconcileConfig::default(),
+        }
+    }
+}
+
+/// `/books` 整批失败（5xx、网络、时限）后改用单 token `/book` 抓取关键 token
+#[derive(Debug, Deserialize, Clone)]
+pub struct FallbackConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 关键 token；为空时批内全部 token 均可回退（按批内顺序）
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    /// 单批最多回退的 token 数，每个占用一次节点限速额度
+    #[serde(default = "default_fallback_tokens")]
+    pub max_tokens: usize,
+    /// 回退阶段的总时限，从 `/books` 失败时起算
+    #[serde(default = "default_fallback_budget")]
+    pub budget_ms: u64,
+    /// 上游 429 时是否回退；默认不回退，避免加重限流
+    #[serde(default)]
+    pub on_429: bool,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tokens: Vec::new(),
+            max_tokens: default_fallback_tokens(),
+            budget_ms: default_fallback_budget(),
+            on_429: false,
+        }
+    }
+}
+
+/// `/books` 返回 payload 类 4xx 时对分批次定位坏 token：单个 token 仍失败且同级另一半成功即隔离，其余正常写入
+#[derive(Debug, Deserialize, Clone)]
+pub struct BisectConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 单批对分最多追加的请求数（同样占用节点限速额度），用尽后本批按失败处理
+    #[serde(default = "default_bisect_requests")]
+    pub max_requests: u32,
+    /// 触发对分的状态码（payload 问题）；413 由 Client 缩小批次，鉴权、超时类 4xx 与具体 token 无关，均不在此列
+    #[serde(default = "default_bisect_statuses")]
+    pub statuses: Vec<u16>,
+}
+
+impl Default for BisectConfig {
+    fn default() -> Self {
+        Self { enabled: true, max_requests: default_bisect_requests(), statuses: default_bisect_statuses() }
+    }
+}
+
+/// `/books` 重试策略：按错误类别决定是否重试，指数退避加抖动，全部尝试须在时限内完成
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetryPolicy {
+    /// 最多尝试次数（含首次）
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_base_backoff")]
+    pub base_backoff_ms: u64,
+    /// 退避上限，同时限制按 `Retry-After` 等待的时长
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff_ms: u64,
+    /// 抖动比例：实际等待在 [1 - jitter, 1] × 退避时长之间随机
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+    /// 未指定时限时单次调用（含重试）的总时长
+    #[serde(default = "default_retry_budget")]
+    pub budget_ms: u64,
+    #[serde(default = "default_true")]
+    pub on_connect: bool,
+    #[serde(default = "default_true")]
+    pub on_timeout: bool,
+    #[serde(default = "default_true")]
+    pub on_429: bool,
+    #[serde(default = "default_true")]
+    pub on_5xx: bool,
+    #[serde(default)]
+    pub on_4xx: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            base_backoff_ms: default_base_backoff(),
+            max_backoff_ms: default_max_backoff(),
+            jitter: default_jitter(),
+            budget_ms: default_retry_budget(),
+            on_connect: true,
+            on_timeout: true,
+            on_429: true,
+            on_5xx: true,
+            on_4xx: false,
+        }
+    }
 }
 
 fn default_plan_horizon() -> u64 { 5 }
+fn default_slice_deadline() -> u64 { 2500 }
+fn default_batch_min() -> usize { 1 }
+fn default_batch_max() -> usize { 500 }
+fn default_batch_increase() -> usize { 1 }
+fn default_batch_decrease() -> f64 { 0.5 }
+fn default_target_latency() -> u64 { 800 }
+fn default_ceiling_recover_after() -> u32 { 100 }
+fn default_probe_interval() -> u64 { 2000 }
+fn default_probe_timeout() -> u64 { 500 }
+fn default_down_after() -> u32 { 2 }
+fn default_up_after() -> u32 { 3 }
+fn default_ack_window() -> usize { 20 }
+fn default_min_ack_success() -> f64 { 0.5 }
+fn default_leader_key() -> String { "poly-ob:leader".into() }
+fn default_lease_ttl() -> u64 { 3000 }
+fn default_true() -> bool { true }
+fn default_reload_interval() -> u64 { 2000 }
+fn default_market_interval() -> u64 { 300 }
+fn default_floor_refresh() -> u64 { 30000 }
+fn default_change_alpha() -> f64 { 0.2 }
+fn default_bias_replan() -> u64 { 30 }
+fn default_watchdog_interval() -> u64 { 5000 }
+fn default_stale_ms() -> u64 { 15000 }
+fn default_stale_channel() -> String { "ob_stale".into() }
+fn default_boost() -> usize { 20 }
+fn default_quarantine_sync() -> u64 { 2000 }
+fn default_revalidate() -> u64 { 600 }
+fn default_max_attempts() -> u32 { 3 }
+fn default_base_backoff() -> u64 { 50 }
+fn default_max_backoff() -> u64 { 1000 }
+fn default_jitter() -> f64 { 0.5 }
+fn default_retry_budget() -> u64 { 2500 }
+fn default_bisect_requests() -> u32 { 24 }
+fn default_bisect_statuses() -> Vec<u16> { vec![400, 404, 422] }
+fn default_depth_levels() -> usize { 5 }
+fn default_band_cents() -> u32 { 5 }
+fn default_fallback_tokens() -> usize { 5 }
+fn default_fallback_budget() -> u64 { 400 }
+fn default_ws_url() -> String { "wss://ws-subscriptions-clob.polymarket.com/ws/market".into() }
+fn default_assign_ttl() -> u64 { 30000 }
+fn default_resubscribe() -> u64 { 2000 }
+fn default_ws_ping() -> u64 { 10 }
+fn default_ws_reconnect() -> u64 { 1000 }
+fn default_reconcile_lag() -> i64 { 2000 }
+fn default_divergence_channel() -> String { "ob_divergence".into() }
 fn default_capacity() -> u32 { 20 }
 fn default_bind() -> String { "0.0.0.0:3000".into() }
+fn default_heartbeat() -> u64 { 1000 }
+fn default_node_ttl() -> u64 { 3000 }
+
+fn read_config(path: &str) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|source| PolyObError::ConfigRead { path: path.to_string(), source })
+}
 
 pub fn load_client(path: &str) -> Result<ClientConfig> {
-    let s = std::fs::read_to_string(path)?;
-    Ok(toml::from_str(&s)?)
+    parse_client(&read_config(path)?)
+}
+
+pub fn parse_client(s: &str) -> Result<ClientConfig> {
+    Ok(toml::from_str(s)?)
 }
 
 pub fn load_fetch(path: &str) -> Result<FetchConfig> {
-    let s = std::fs::read_to_string(path)?;
-    Ok(toml::from_str(&s)?)
+    Ok(toml::from_str(&read_config(path)?)?)
 }
 
 
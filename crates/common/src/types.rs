@@ -1,25 +1,87 @@
-use serde::{Deserialize, Serialize};
+use crate::decimal::Decimal;
+use crate::settings::MarketFilter;
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 单个价位；价格与数量为定点小数，序列化为规范写法（见 [`Decimal`]），上游的规范写法原样往返
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BookLevel {
-    pub price: String,
-    pub size: String,
+    pub price: Decimal,
+    pub size: Decimal,
 }
 
+/// 订单簿快照。反序列化时即按价格排序：bids 升序、asks 降序（最优价都在末尾，与 REST 一致）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBookSnapshot {
     pub market: String,
     pub asset_id: String,
     pub hash: String,
     pub timestamp: String,
+    #[serde(deserialize_with = "de_bids")]
     pub bids: Vec<BookLevel>,
+    #[serde(deserialize_with = "de_asks")]
     pub asks: Vec<BookLevel>,
     #[serde(default)]
-    pub min_order_size: Option<String>,
+    pub min_order_size: Option<Decimal>,
     #[serde(default)]
     pub neg_risk: Option<bool>,
     #[serde(default)]
-    pub tick_size: Option<String>,
+    pub tick_size: Option<Decimal>,
+}
+
+impl OrderBookSnapshot {
+    /// 价格换算为 tick 数；缺少 `tick_size` 或价格不在 tick 网格上时为 `None`
+    pub fn price_ticks(&self, price: Decimal) -> Option<i64> {
+        price.ticks(self.tick_size?)
+    }
+
+    /// 不在 tick 网格上的价位数（缺少 `tick_size` 时为 0）
+    pub fn off_tick_levels(&self) -> usize {
+        let Some(tick) = self.tick_size else { return 0 };
+        self.bids.iter().chain(&self.asks).filter(|l| l.price.ticks(tick).is_none()).count()
+    }
+}
+
+/// bids 按价格升序（最优价在末尾）
+pub fn sort_bids(levels: &mut [BookLevel]) {
+    levels.sort_by_key(|l| l.price);
+}
+
+/// asks 按价格降序（最优价在末尾）
+pub fn sort_asks(levels: &mut [BookLevel]) {
+    levels.sort_by_key(|l| std::cmp::Reverse(l.price));
+}
+
+pub fn de_bids<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<BookLevel>, D::Error> {
+    let mut levels = de_levels(d)?;
+    sort_bids(&mut levels);
+    Ok(levels)
+}
+
+pub fn de_asks<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<BookLevel>, D::Error> {
+    let mut levels = de_levels(d)?;
+    sort_asks(&mut levels);
+    Ok(levels)
+}
+
+/// 逐个解析价位：个别价位无法解析（如超出定点小数范围）时跳过并告警，不让整批 `/books` 响应失败
+fn de_levels<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<BookLevel>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawLevel {
+        Ok(BookLevel),
+        Bad(serde_json::Value),
+    }
+    let raw = Vec::<RawLevel>::deserialize(d)?;
+    Ok(raw
+        .into_iter()
+        .filter_map(|l| match l {
+            RawLevel::Ok(level) => Some(level),
+            RawLevel::Bad(v) => {
+                tracing::warn!(level = %v, "skipping unparseable book level");
+                None
+            }
+        })
+        .collect())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +94,159 @@ pub struct BookTokenParam {
     pub token_id: String,
 }
 
+/// Fetch 节点注册信息：`node:{node_id}`（带 TTL 的 JSON），索引集合 `nodes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    pub node_id: String,
+    pub addr: String,
+    pub capacity_rps: u32,
+    pub version: String,
+    pub started_at_ms: i64,
+    pub heartbeat_ms: i64,
+}
+
+/// `GET /markets` 的一页；`next_cursor` 为 `LTE=` 表示已到末页
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarketsPage {
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    #[serde(default)]
+    pub data: Vec<Market>,
+}
+
+/// CLOB 市场（仅保留 token 发现用到的字段）
+#[derive(Debug, Clone, Deserialize)]
+pub struct Market {
+    pub condition_id: String,
+    #[serde(default)]
+    pub question: String,
+    #[serde(default)]
+    pub tokens: Vec<MarketToken>,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub enable_order_book: Option<bool>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub end_date_iso: Option<String>,
+    /// 成交量；接口可能以字符串或数字返回，也可能缺失
+    #[serde(default, deserialize_with = "de_opt_f64")]
+    pub volume: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarketToken {
+    pub token_id: String,
+    #[serde(default)]
+    pub outcome: String,
+}
+
+impl Market {
+    /// 是否满足发现过滤条件；未开启订单簿的市场总是排除
+    pub fn matches(&self, f: &MarketFilter) -> bool {
+        if self.enable_order_book == Some(false) || (f.active_only && !self.active) || (!f.include_closed && self.closed) {
+            return false;
+        }
+        if !f.tags.is_empty() {
+            let tags = self.tags.as_deref().unwrap_or_default();
+            if !tags.iter().any(|t| f.tags.iter().any(|w| w.eq_ignore_ascii_case(t))) {
+                return false;
+            }
+        }
+        if f.end_after.is_some() || f.end_before.is_some() {
+            // 设置了结束时间范围时，没有（或无法解析）结束时间的市场不纳入
+            let Some(end) = self.end_date_iso.as_deref().and_then(parse_end_date) else {
+                return false;
+            };
+            if f.end_after.is_some_and(|t| end < t) || f.end_before.is_some_and(|t| end > t) {
+                return false;
+            }
+        }
+        if let Some(min) = f.min_volume {
+            if self.volume.unwrap_or(0.0) < min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 结束时间为 RFC 3339，个别市场只给日期（按当日 00:00 UTC）
+fn parse_end_date(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    if let Ok(t) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Some(t.to_utc());
+    }
+    let d = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+    Some(d.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+fn de_opt_f64<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f64>, D::Error> {
+    Ok(match Option::<serde_json::Value>::deserialize(d)? {
+        Some(serde_json::Value::Number(n)) => n.as_f64(),
+        Some(serde_json::Value::String(s)) => s.parse().ok(),
+        _ => None,
+    })
+}
+
+/// 陈旧告警：一次巡检中新变陈旧与已恢复的 token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaleAlert {
+    pub at_ms: i64,
+    pub threshold_ms: u64,
+    pub stale: Vec<StaleToken>,
+    pub recovered: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaleToken {
+    pub token_id: String,
+    /// 最近一次确认新鲜的时刻；从未写入过快照时为 `None`
+    pub last_fresh_ms: Option<i64>,
+    pub age_ms: i64,
+}
+
+/// 被隔离的 token：单独请求 `/books` 仍返回 4xx
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedToken {
+    pub token_id: String,
+    pub http_status: Option<u16>,
+    /// 上游返回的错误文本
+    pub error: String,
+    /// 判定该 token 的 Fetch 节点
+    pub node_id: String,
+    pub at_ms: i64,
+}
+
+/// REST 快照与 WS 本地簿的分歧类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DivergenceKind {
+    /// 同一时间戳、价位相同，仅 hash 不同
+    Hash,
+    /// 同一时间戳、价位内容不同
+    Levels,
+    /// REST 领先超过 `lag_ms` 且内容不同：WS 漏收或滞后
+    Lagging,
+}
+
+/// 分歧事件：发布到 `[ws.reconcile] channel`，计数累加到 Redis 哈希 `divergence`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DivergenceEvent {
+    pub token_id: String,
+    pub node_id: String,
+    pub kind: DivergenceKind,
+    pub ws_hash: String,
+    pub rest_hash: String,
+    pub ws_timestamp: String,
+    pub rest_timestamp: String,
+    /// 该 token 累计分歧次数（全部节点）
+    pub count: u64,
+    pub at_ms: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RedisBookRecord {
     pub bids: String,
@@ -43,3 +258,21 @@ pub struct RedisBookRecord {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_sorts_levels_and_skips_unparseable_ones() {
+        let json = r#"{
+            "market": "m", "asset_id": "a", "hash": "h", "timestamp": "1",
+            "bids": [{"price": "0.49", "size": "5"}, {"price": 0.47, "size": 10}, {"price": "bogus", "size": "1"}],
+            "asks": [{"price": ".51", "size": "99999999999999999999"}, {"price": "0.52", "size": "3"}, {"price": "0.51", "size": "2"}]
+        }"#;
+        let ob: OrderBookSnapshot = serde_json::from_str(json).unwrap();
+        let prices = |ls: &[BookLevel]| ls.iter().map(|l| l.price.to_string()).collect::<Vec<_>>();
+        assert_eq!(prices(&ob.bids), ["0.47", "0.49"]);
+        assert_eq!(prices(&ob.asks), ["0.52", "0.51"]);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+/// 令牌桶：按 `rate` 每秒补充，容量 `burst`；取不到令牌的请求直接拒绝
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+    /// 最近 1 秒内成功取得令牌的时刻，用于上报实际速率
+    recent: VecDeque<Instant>,
+}
+
+pub type SharedLimiter = Arc<Mutex<TokenBucket>>;
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self { rate, burst, tokens: burst, last: Instant::now(), recent: VecDeque::new() }
+    }
+
+    /// 以 `capacity_rps` 构造：速率与容量相同，允许最多 1 秒的突发
+    pub fn shared(capacity_rps: u32) -> SharedLimiter {
+        let rate = capacity_rps as f64;
+        Arc::new(Mutex::new(Self::new(rate, rate.max(1.0))))
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_n(1)
+    }
+
+    /// 一次取 `n` 个令牌，不足时一个也不取
+    pub fn try_acquire_n(&mut self, n: u32) -> bool {
+        let now = Instant::now();
+        self.refill(now);
+        if self.tokens < n as f64 {
+            return false;
+        }
+        self.tokens -= n as f64;
+        self.recent.extend((0..n).map(|_| now));
+        self.prune(now);
+        true
+    }
+
+    /// 最近 1 秒实际放行的请求数
+    pub fn current_rps(&mut self) -> u32 {
+        self.prune(Instant::now());
+        self.recent.len() as u32
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while self.recent.front().is_some_and(|&t| now.saturating_duration_since(t) >= Duration::from_secs(1)) {
+            self.recent.pop_front();
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let dt = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + dt * self.rate).min(self.burst);
+        self.last = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_n_is_all_or_nothing() {
+        let mut b = TokenBucket::new(10.0, 3.0);
+        assert!(b.try_acquire());
+        assert!(!b.try_acquire_n(3));
+        assert!(b.try_acquire_n(2));
+        assert!(!b.try_acquire());
+        assert_eq!(b.current_rps(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_at_rate_up_to_burst() {
+        let mut b = TokenBucket::new(10.0, 5.0);
+        assert_eq!((0..6).filter(|_| b.try_acquire()).count(), 5, "初始为满桶");
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(b.try_acquire(), "100ms 补充 1 个");
+        assert!(!b.try_acquire());
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert!(b.try_acquire_n(2));
+        assert!(!b.try_acquire(), "不足 1 个的部分留待累积");
+        tokio::time::advance(Duration::from_millis(50)).await;
+        assert!(b.try_acquire());
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!((0..10).filter(|_| b.try_acquire()).count(), 5, "长时间空闲也不超过 burst");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_allows_one_second_of_burst_and_reports_rate() {
+        let limiter = TokenBucket::shared(4);
+        {
+            let mut b = limiter.lock().unwrap();
+            assert_eq!((0..8).filter(|_| b.try_acquire()).count(), 4);
+            assert_eq!(b.current_rps(), 4);
+        }
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let mut b = limiter.lock().unwrap();
+        assert_eq!(b.current_rps(), 0);
+        assert!(b.try_acquire_n(4));
+    }
+}
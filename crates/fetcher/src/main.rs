@@ -1,11 +1,28 @@
+mod limiter;
+mod reconcile;
+mod session;
+mod stats;
+#[cfg(test)]
+mod testutil;
+mod ws;
+
 use anyhow::Result;
+use poly_ob_common::analytics::BookStats;
+use poly_ob_common::error::PolyObError;
 use poly_ob_common::http::HttpClient;
-use poly_ob_common::redisx::RedisClient;
-use poly_ob_common::settings::{load_fetch, FetchConfig};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use limiter::{SharedLimiter, TokenBucket};
+use reconcile::{reconcile, Divergence, SharedBooks};
+use poly_ob_common::protocol::{read_frame, write_frame, Ack, AckStatus, Command, NodeInfo, NodeStatus, Request};
+use poly_ob_common::redisx::{CasOutcome, RedisClient};
+use poly_ob_common::settings::{load_fetch, AnalyticsConfig, BisectConfig, FallbackConfig, FetchConfig, ReconcileConfig};
+use poly_ob_common::types::{DivergenceEvent, NodeRecord, OrderBookSnapshot, QuarantinedToken};
+use session::{Session, SharedSession};
+use stats::{NodeStats, SharedStats};
+use std::collections::HashSet;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
 use tokio::time::{Duration, Instant};
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,86 +32,813 @@ async fn main() -> Result<()> {
 }
 
 async fn run_fetcher(cfg: FetchConfig) -> Result<()> {
-    let http = HttpClient::new(&cfg.base_url)?;
+    let http = HttpClient::new(&cfg.base_url)?.with_retry(cfg.retry.clone());
     let redis = RedisClient::connect(&cfg.redis_url).await?;
 
+    let node = NodeCtx::new(&cfg, http, redis);
+
     let listener = TcpListener::bind(&cfg.bind_addr).await?;
-    info!("fetch node {} listening {}", cfg.node_id, cfg.bind_addr);
+    info!("fetch node {} listening {} (capacity {} rps)", cfg.node_id, cfg.bind_addr, cfg.capacity_rps);
 
-    // 上次 payload
-    let mut last_tokens: Vec<String> = Vec::new();
+    if let Some(books) = &node.ws_books {
+        let ws = ws::ws_loop(cfg.ws.clone(), cfg.analytics.clone(), node.redis.clone(), node.session.clone(), books.clone());
+        tokio::spawn(ws);
+    }
+
+    match &cfg.advertise_addr {
+        Some(addr) => {
+            tokio::spawn(heartbeat_loop(cfg.clone(), addr.clone(), node.redis.clone()));
+        }
+        None => info!("advertise_addr not set, skip registry"),
+    }
 
     loop {
-        let (mut socket, peer) = listener.accept().await?;
-        let http = http.clone();
-        let mut redis = redis.clone();
-        let mut last_tokens_ref = last_tokens.clone();
+        let (socket, peer) = tokio::select! {
+            acc = listener.accept() => acc?,
+            _ = tokio::signal::ctrl_c() => {
+                // 主动注销，Client 无需等待 TTL 过期即可重新分配
+                if cfg.advertise_addr.is_some() {
+                    let mut redis = node.redis.clone();
+                    if let Err(e) = redis.unregister_node(&cfg.node_id).await {
+                        warn!("unregister failed: {}", e);
+                    }
+                }
+                info!("fetch node {} shutting down", cfg.node_id);
+                return Ok(());
+            }
+        };
+        let _ = socket.set_nodelay(true);
+        let node = node.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_conn(&mut socket, &http, &mut redis, &mut last_tokens_ref).await {
+            if let Err(e) = handle_conn(socket, node).await {
                 error!("handle_conn from {} error: {}", peer, e);
             }
         });
     }
 }
 
-async fn handle_conn(sock: &mut TcpStream, http: &HttpClient, redis: &mut RedisClient, last: &mut Vec<String>) -> Result<()> {
-    // 读取长度前缀（容错：若 EOF，静默返回）
-    let mut len_buf = [0u8; 4];
-    if let Err(e) = sock.read_exact(&mut len_buf).await {
-        if e.kind() == std::io::ErrorKind::UnexpectedEof || e.kind() == std::io::ErrorKind::WouldBlock {
-            return Ok(());
-        } else {
-            return Err(e.into());
-        }
-    }
-    let len = u32::from_be_bytes(len_buf) as usize;
-    let mut buf = vec![0u8; len];
-    sock.read_exact(&mut buf).await?;
-    let msg: serde_json::Value = serde_json::from_slice(&buf)?;
-
-    let tokens = if let Some(arr) = msg.get("tokens").and_then(|v| v.as_array()) {
-        let v: Vec<String> = arr.iter().filter_map(|x| x.as_str().map(|s| s.to_string())).collect();
-        if !v.is_empty() { *last = v.clone(); }
-        if last.is_empty() { anyhow::bail!("empty tokens payload and no last state") }
-        last.clone()
-    } else {
-        if last.is_empty() { anyhow::bail!("no tokens and empty last state") } else { last.clone() }
+/// 定期续期 Redis 注册记录，供 Client 发现；进程退出后由 TTL 自然过期
+async fn heartbeat_loop(cfg: FetchConfig, addr: String, mut redis: RedisClient) {
+    let mut rec = NodeRecord {
+        node_id: cfg.node_id.clone(),
+        addr,
+        capacity_rps: cfg.capacity_rps,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        started_at_ms: chrono::Utc::now().timestamp_millis(),
+        heartbeat_ms: 0,
     };
+    let mut tick = tokio::time::interval(Duration::from_millis(cfg.heartbeat_ms.max(100)));
+    loop {
+        tick.tick().await;
+        rec.heartbeat_ms = chrono::Utc::now().timestamp_millis();
+        if let Err(e) = redis.register_node(&rec, cfg.node_ttl_ms).await {
+            warn!("node heartbeat failed: {}", e);
+        }
+    }
+}
+
+/// 节点共享上下文，每个连接持有一份克隆
+#[derive(Clone)]
+struct NodeCtx {
+    http: HttpClient,
+    redis: RedisClient,
+    session: SharedSession,
+    limiter: SharedLimiter,
+    stats: SharedStats,
+    info: NodeInfo,
+    /// 单批 `/books`（含重试）的总时限
+    retry_budget: Duration,
+    bisect: BisectConfig,
+    fallback: FallbackConfig,
+    /// 开启 WS 接入时的本地簿，REST 快照据此对账
+    ws_books: Option<SharedBooks>,
+    reconcile: ReconcileConfig,
+    /// 随快照写入的派生指标
+    analytics: AnalyticsConfig,
+}
+
+impl NodeCtx {
+    fn new(cfg: &FetchConfig, http: HttpClient, redis: RedisClient) -> Self {
+        Self {
+            http,
+            redis,
+            session: Session::shared(),
+            limiter: TokenBucket::shared(cfg.capacity_rps),
+            stats: NodeStats::shared(),
+            info: NodeInfo { node_id: cfg.node_id.clone(), capacity_rps: cfg.capacity_rps },
+            retry_budget: Duration::from_millis(cfg.retry.budget_ms),
+            bisect: cfg.bisect.clone(),
+            fallback: cfg.fallback.clone(),
+            ws_books: cfg.ws.enabled.then(SharedBooks::default),
+            reconcile: cfg.ws.reconcile.clone(),
+            analytics: cfg.analytics.clone(),
+        }
+    }
+
+    /// 汇总节点运行状态；Redis 连通性以带超时的 PING 实测
+    async fn status(&self) -> NodeStatus {
+        let mut redis = self.redis.clone();
+        let redis_ok = matches!(tokio::time::timeout(Duration::from_millis(150), redis.ping()).await, Ok(Ok(())));
+        let current_rps = self.limiter.lock().unwrap().current_rps();
+        NodeStatus {
+            node_id: self.info.node_id.clone(),
+            capacity_rps: self.info.capacity_rps,
+            uptime_ms: self.stats.uptime_ms(),
+            redis_ok,
+            last_upstream: self.stats.last_upstream(),
+            in_flight: self.stats.in_flight(),
+            current_rps,
+            rate_usage: current_rps as f64 / self.info.capacity_rps.max(1) as f64,
+        }
+    }
+}
 
+/// 长连接：循环读取指令帧，每条指令独立执行，Ack 经写任务按完成顺序回写
+async fn handle_conn(sock: TcpStream, node: NodeCtx) -> Result<()> {
+    let (mut rd, mut wr) = sock.into_split();
+    let (ack_tx, mut ack_rx) = mpsc::unbounded_channel::<Ack>();
+    let writer = tokio::spawn(async move {
+        while let Some(ack) = ack_rx.recv().await {
+            if let Err(e) = write_frame(&mut wr, &ack).await {
+                error!("write ack failed: {}", e);
+                break;
+            }
+        }
+    });
+
+    while let Some(req) = read_frame::<_, Request>(&mut rd).await? {
+        let Request { id, fence, deadline_ms, cmd } = req;
+        match cmd {
+            Command::Hello => {
+                let _ = ack_tx.send(Ack { node: Some(node.info.clone()), ..Ack::ok(id) });
+                continue;
+            }
+            Command::Ping => {
+                let _ = ack_tx.send(Ack::ok(id));
+                continue;
+            }
+            Command::Status => {
+                let (node, ack_tx) = (node.clone(), ack_tx.clone());
+                tokio::spawn(async move {
+                    let _ = ack_tx.send(Ack { report: Some(node.status().await), ..Ack::ok(id) });
+                });
+                continue;
+            }
+            _ => {}
+        }
+        // 状态更新在读循环内串行完成，保证同一连接上指令顺序生效
+        let applied = {
+            let mut session = node.session.lock().unwrap();
+            // 已失去租约的旧 leader 发来的调度指令一律拒绝
+            if let Err(seen) = session.check_fence(fence) {
+                warn!(id, ?fence, seen, "stale fencing token");
+                let _ = ack_tx.send(Ack::stale_fence(id, seen));
+                continue;
+            }
+            // 超出 capacity_rps 的抓取指令直接拒绝，不改变会话状态
+            if cmd.triggers_fetch() && !node.limiter.lock().unwrap().try_acquire() {
+                warn!(id, "rate limited");
+                let _ = ack_tx.send(Ack::rate_limited(id));
+                continue;
+            }
+            session.apply(&cmd)
+        };
+        let tokens = match applied {
+            Ok(Some(tokens)) => tokens,
+            Ok(None) => {
+                let _ = ack_tx.send(Ack::ok(id));
+                continue;
+            }
+            Err(e) => {
+                let _ = ack_tx.send(Ack::error(id, e));
+                continue;
+            }
+        };
+        let node = node.clone();
+        let ack_tx = ack_tx.clone();
+        tokio::spawn(async move {
+            let ack = fetch_batch(id, &tokens, &node, deadline_ms).await;
+            let _ = ack_tx.send(ack);
+        });
+    }
+
+    // 对端关闭：等待在途指令的 Ack 写完
+    drop(ack_tx);
+    let _ = writer.await;
+    Ok(())
+}
+
+/// 抓取一批并写入 Redis。时限取指令携带的 `deadline_ms` 与本地 `retry.budget_ms` 中较小者，
+/// 到期未完成的上游请求（含重试、对分与回退）一律取消
+async fn fetch_batch(id: u64, tokens: &[String], node: &NodeCtx, deadline_ms: Option<u64>) -> Ack {
+    let _in_flight = node.stats.begin();
+    let mut redis = node.redis.clone();
     let start = Instant::now();
     // 批量请求 /books，打印关键定位信息
-    let sample = tokens.get(0).cloned().unwrap_or_default();
+    let sample = tokens.first().cloned().unwrap_or_default();
     let sample2 = tokens.get(1).cloned().unwrap_or_default();
     tracing::info!(
+        id,
         size = tokens.len(),
         %sample,
         %sample2,
         "dispatch batch"
     );
-    let books = match http.get_books(&tokens).await {
-        Ok(b) => b,
-        Err(e) => {
-            // 打印服务端返回文本（已在 HttpClient 中拼入状态与文本），不再做 fallback
-            tracing::error!(err = %e, size = tokens.len(), "books endpoint failed");
-            return Ok(());
+    let budget = deadline_ms.map_or(node.retry_budget, |ms| Duration::from_millis(ms).min(node.retry_budget));
+    let deadline = start + budget;
+    // 时限已到的指令不再发起上游请求
+    let fetched = match budget.is_zero() {
+        true => None,
+        false => tokio::time::timeout_at(deadline, async {
+            let mut f = fetch_isolating(tokens, node, deadline).await;
+            if let Some(e) = &f.failure {
+                if node.fallback.enabled && fallback_applies(e, &node.fallback) {
+                    fetch_fallback(id, tokens, &mut f.books, node, deadline).await;
+                }
+            }
+            f
+        })
+        .await
+        .ok(),
+    };
+    let Some(BatchFetch { books, bad, failure }) = fetched else {
+        warn!(id, size = tokens.len(), budget_ms = budget.as_millis() as u64, "batch deadline exceeded, upstream requests cancelled");
+        node.stats.record_upstream(None, Some("deadline exceeded".into()));
+        let mut ack = Ack::deadline_exceeded(id, budget.as_millis() as u64);
+        ack.latency_ms = start.elapsed().as_millis() as u64;
+        return ack;
+    };
+    let mut ack = match &failure {
+        None => {
+            node.stats.record_upstream(Some(200), None);
+            Ack::ok(id)
+        }
+        Some(e) => {
+            // 打印服务端返回文本（已在 HttpClient 中拼入状态与文本）；对分已取到的子批照常写入
+            tracing::error!(id, err = %e, size = tokens.len(), fetched = books.len(), "books endpoint failed");
+            node.stats.record_upstream(e.status(), Some(e.to_string()));
+            let status = match e {
+                PolyObError::DeadlineExceeded { .. } => AckStatus::DeadlineExceeded,
+                _ => AckStatus::Error,
+            };
+            Ack { status, http_status: e.status(), ..Ack::error(id, e) }
         }
     };
+    if !bad.is_empty() {
+        for t in &bad {
+            warn!(id, token = %t.token_id, http_status = ?t.http_status, err = %t.error, "token quarantined");
+        }
+        if let Err(e) = redis.quarantine(&bad).await {
+            tracing::error!(id, err = %e, "quarantine write failed");
+        }
+        ack.quarantined = bad;
+    }
+    // 与 WS 本地簿分歧的 token 以 REST 快照强制修复 Redis 副本（hash 相同也覆盖）
+    let mut repair = HashSet::new();
+    if let (Some(ws_books), true) = (&node.ws_books, node.reconcile.enabled) {
+        for ob in &books {
+            if let Some(d) = reconcile(&node.reconcile, ws_books, ob) {
+                report_divergence(&mut redis, node, ob, d).await;
+                repair.insert(ob.asset_id.clone());
+            }
+        }
+    }
     let now_ms = chrono::Utc::now().timestamp_millis();
+    ack.fetched = books.len();
     for ob in books.iter() {
-        let res = redis.cas_upsert_book(ob, now_ms).await?;
-        if res == "updated" {
-            // 可选：发布实时更新到频道，供可视化订阅
-            let _ = redis.publish_update("ob_updates", ob).await;
+        let stats = BookStats::configured(&node.analytics, ob);
+        let res = match repair.contains(&ob.asset_id) {
+            true => redis.repair_book(ob, now_ms, stats.as_ref()).await,
+            false => redis.cas_upsert_book(ob, now_ms, stats.as_ref()).await,
+        };
+        match res {
+            Ok(CasOutcome::Updated) => {
+                ack.updated += 1;
+                ack.changed.push(ob.asset_id.clone());
+                // 可选：发布实时更新到频道，供可视化订阅
+                let _ = redis.publish_update("ob_updates", ob).await;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!(id, token = %ob.asset_id, err = %e, "redis cas failed");
+                ack.status = AckStatus::Error;
+                ack.error = Some(e.to_string());
+            }
         }
     }
     let elapsed = start.elapsed();
-    tracing::info!(fetched = books.len(), took_ms = %elapsed.as_millis(), "batch done");
+    ack.latency_ms = elapsed.as_millis() as u64;
+    tracing::info!(id, fetched = books.len(), updated = ack.updated, took_ms = %elapsed.as_millis(), "batch done");
+    ack
+}
 
-    // 回复简单 OK
-    let ok = b"OK";
-    sock.write_all(ok).await?;
-    sock.shutdown().await?;
-    Ok(())
+/// 累加分歧计数并发布事件
+async fn report_divergence(redis: &mut RedisClient, node: &NodeCtx, rest: &OrderBookSnapshot, d: Divergence) {
+    let count = match redis.count_divergence(&rest.asset_id).await {
+        Ok(n) => n,
+        Err(e) => {
+            warn!(token = %rest.asset_id, "divergence count failed: {}", e);
+            0
+        }
+    };
+    let ev = DivergenceEvent {
+        token_id: rest.asset_id.clone(),
+        node_id: node.info.node_id.clone(),
+        kind: d.kind,
+        ws_hash: d.ws_hash,
+        rest_hash: rest.hash.clone(),
+        ws_timestamp: d.ws_timestamp,
+        rest_timestamp: rest.timestamp.clone(),
+        count,
+        at_ms: chrono::Utc::now().timestamp_millis(),
+    };
+    warn!(token = %ev.token_id, kind = ?ev.kind, ws_ts = %ev.ws_timestamp, rest_ts = %ev.rest_timestamp, count, "ws book diverged from rest, repaired");
+    if let Err(e) = redis.publish_json(&node.reconcile.channel, &ev).await {
+        warn!("divergence publish failed: {}", e);
+    }
 }
 
+/// 一批的抓取结果：对分时已成功的子批照常返回，未能完成的原因记在 `failure`
+struct BatchFetch {
+    books: Vec<OrderBookSnapshot>,
+    bad: Vec<QuarantinedToken>,
+    failure: Option<PolyObError>,
+}
 
+/// 抓取一批：`/books` 返回 payload 类 4xx（`[bisect] statuses`）时对分重发，两半同时请求；
+/// 单个 token 仍失败且同级另一半成功时才判为坏 token，两半都失败（如整体 404）则不隔离、本批按失败处理。
+/// 对分追加的请求同样占用节点限速额度，额度或 `max_requests` 用尽时本批按失败处理；
+/// 其他错误（413、其余 4xx、5xx、网络、时限）不对分，立即结束并带回状态码
+async fn fetch_isolating(tokens: &[String], node: &NodeCtx, deadline: Instant) -> BatchFetch {
+    // 重试同样占用节点限速额度，拿不到令牌即放弃重试
+    let permit = || node.limiter.lock().unwrap().try_acquire();
+    let mut out = BatchFetch { books: Vec::new(), bad: Vec::new(), failure: None };
+    let err = match node.http.get_books_with(tokens, deadline, permit).await {
+        Ok(books) => {
+            out.books = books;
+            return out;
+        }
+        Err(e) => e,
+    };
+    if !bisectable(&err, &node.bisect) || tokens.len() < 2 {
+        out.failure = Some(err);
+        return out;
+    }
+    let mut extra = 0;
+    let mut pending = vec![(tokens.to_vec(), err)];
+    while let Some((part, err)) = pending.pop() {
+        // 两半的额度一次取足，避免只取到一个时白白消耗
+        if extra + 2 > node.bisect.max_requests || !node.limiter.lock().unwrap().try_acquire_n(2) {
+            warn!(size = part.len(), extra, "bisection budget exhausted");
+            out.failure.get_or_insert(err);
+            continue;
+        }
+        extra += 2;
+        debug!(size = part.len(), status = ?err.status(), "bisecting batch");
+        let (left, right) = part.split_at(part.len() / 2);
+        let (l, r) = tokio::join!(
+            node.http.get_books_with(left, deadline, permit),
+            node.http.get_books_with(right, deadline, permit),
+        );
+        let sibling_ok = [r.is_ok(), l.is_ok()];
+        for ((half, res), sibling_ok) in [(left, l), (right, r)].into_iter().zip(sibling_ok) {
+            let err = match res {
+                Ok(books) => {
+                    out.books.extend(books);
+                    continue;
+                }
+                Err(e) => e,
+            };
+            if !bisectable(&err, &node.bisect) {
+                out.failure = Some(err);
+                return out;
+            }
+            match (half.len(), sibling_ok) {
+                (1, true) => out.bad.push(QuarantinedToken {
+                    token_id: half[0].clone(),
+                    http_status: err.status(),
+                    error: err.to_string(),
+                    node_id: node.info.node_id.clone(),
+                    at_ms: chrono::Utc::now().timestamp_millis(),
+                }),
+                // 同级也失败：无法确认是该 token 的问题
+                (1, false) => {
+                    out.failure.get_or_insert(err);
+                }
+                _ => pending.push((half.to_vec(), err)),
+            }
+        }
+    }
+    out
+}
+
+/// 仅 payload 类 4xx 对分；413 交回 Client 缩小批次，401/403/408 等与具体 token 无关
+fn bisectable(e: &PolyObError, cfg: &BisectConfig) -> bool {
+    cfg.enabled && matches!(e, PolyObError::ClientStatus(_)) && e.status().is_some_and(|s| cfg.statuses.contains(&s))
+}
+
+/// 4xx 交给对分处理；429 仅在配置允许时回退
+fn fallback_applies(e: &PolyObError, cfg: &FallbackConfig) -> bool {
+    match e {
+        PolyObError::ClientStatus(_) => false,
+        PolyObError::RateLimited(_) => cfg.on_429,
+        PolyObError::DeadlineExceeded { last, .. } => fallback_applies(last, cfg),
+        _ => true,
+    }
+}
+
+/// `/books` 失败后逐个 `/book` 抓取批内尚未取到的关键 token：并发发出，每个先取一次节点限速额度，
+/// 取不到即停止；全部请求须在 `budget_ms` 与批次时限内完成，超时的放弃
+async fn fetch_fallback(id: u64, tokens: &[String], books: &mut Vec<OrderBookSnapshot>, node: &NodeCtx, deadline: Instant) {
+    let cfg = &node.fallback;
+    let fetched: HashSet<&str> = books.iter().map(|b| b.asset_id.as_str()).collect();
+    let wanted: Vec<String> = tokens
+        .iter()
+        .filter(|t| !fetched.contains(t.as_str()) && (cfg.tokens.is_empty() || cfg.tokens.contains(t)))
+        .take(cfg.max_tokens)
+        .cloned()
+        .collect();
+    if wanted.is_empty() {
+        return;
+    }
+    let deadline = deadline.min(Instant::now() + Duration::from_millis(cfg.budget_ms));
+    let mut set = tokio::task::JoinSet::new();
+    for t in &wanted {
+        if !node.limiter.lock().unwrap().try_acquire() {
+            warn!(id, "fallback stopped by node rate limit");
+            break;
+        }
+        let (http, t) = (node.http.clone(), t.clone());
+        set.spawn(async move {
+            let r = tokio::time::timeout_at(deadline, http.get_book(&t)).await;
+            (t, r)
+        });
+    }
+    let mut got = 0;
+    while let Some(joined) = set.join_next().await {
+        let Ok((t, r)) = joined else { continue };
+        match r {
+            Ok(Ok(book)) => {
+                got += 1;
+                books.push(book);
+            }
+            Ok(Err(e)) => warn!(id, token = %t, err = %e, "fallback /book failed"),
+            Err(_) => warn!(id, token = %t, "fallback /book timed out"),
+        }
+    }
+    info!(id, wanted = wanted.len(), got, "fallback via /book");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{MockRedis, Upstream};
+    use poly_ob_common::book::LocalBook;
+    use serde_json::json;
+
+    struct Harness {
+        addr: std::net::SocketAddr,
+        upstream: Upstream,
+        redis: MockRedis,
+        node: NodeCtx,
+    }
+
+    impl Harness {
+        async fn start(status: impl Fn(&[String]) -> u16 + Send + Sync + 'static) -> Self {
+            Self::with_config(json!({}), status).await
+        }
+
+        async fn with_config(overrides: serde_json::Value, status: impl Fn(&[String]) -> u16 + Send + Sync + 'static) -> Self {
+            Self::with_upstream(overrides, Upstream::start(status).await).await
+        }
+
+        /// `overrides` 中的顶层字段覆盖默认测试配置
+        async fn with_upstream(overrides: serde_json::Value, upstream: Upstream) -> Self {
+            let redis = MockRedis::start().await;
+            let mut cfg = json!({
+                "redis_url": redis.url,
+                "base_url": upstream.base,
+                "node_id": "test",
+                "capacity_rps": 1000,
+            });
+            for (k, v) in overrides.as_object().unwrap() {
+                cfg[k] = v.clone();
+            }
+            let cfg: FetchConfig = serde_json::from_value(cfg).unwrap();
+            let http = HttpClient::new(cfg.base_url.clone()).unwrap().with_retry(cfg.retry.clone());
+            let node = NodeCtx::new(&cfg, http, RedisClient::connect(&cfg.redis_url).await.unwrap());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let serving = node.clone();
+            tokio::spawn(async move {
+                while let Ok((sock, _)) = listener.accept().await {
+                    tokio::spawn(handle_conn(sock, serving.clone()));
+                }
+            });
+            Self { addr, upstream, redis, node }
+        }
+
+        async fn connect(&self) -> Conn {
+            Conn { sock: TcpStream::connect(self.addr).await.unwrap(), next_id: 1 }
+        }
+    }
+
+    struct Conn {
+        sock: TcpStream,
+        next_id: u64,
+    }
+
+    impl Conn {
+        async fn call(&mut self, fence: Option<u64>, cmd: Command) -> Ack {
+            self.call_with_deadline(fence, None, cmd).await
+        }
+
+        async fn call_with_deadline(&mut self, fence: Option<u64>, deadline_ms: Option<u64>, cmd: Command) -> Ack {
+            let id = self.next_id;
+            self.next_id += 1;
+            write_frame(&mut self.sock, &Request { id, fence, deadline_ms, cmd }).await.unwrap();
+            let ack: Ack = read_frame(&mut self.sock).await.unwrap().expect("connection closed before ack");
+            assert_eq!(ack.id, id);
+            ack
+        }
+    }
+
+    fn tokens(ts: &[&str]) -> Vec<String> {
+        ts.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn empty_set_replays_last_payload_across_connections() {
+        let h = Harness::start(|_| 200).await;
+        let mut a = h.connect().await;
+        let ack = a.call(None, Command::Set { tokens: tokens(&["t1", "t2"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::Ok, 2));
+        drop(a);
+
+        let mut b = h.connect().await;
+        let ack = b.call(None, Command::Set { tokens: vec![] }).await;
+        assert_eq!((ack.status, ack.fetched, ack.updated), (AckStatus::Ok, 2, 2));
+        assert_eq!(h.upstream.requests(), vec![tokens(&["t1", "t2"]), tokens(&["t1", "t2"])]);
+        assert_eq!(h.redis.calls("EVALSHA").len(), 4);
+    }
+
+    #[tokio::test]
+    async fn append_dedups_against_last_payload() {
+        let h = Harness::start(|_| 200).await;
+        let mut c = h.connect().await;
+        c.call(None, Command::Set { tokens: tokens(&["t1", "t2"]) }).await;
+        let ack = c.call(None, Command::Append { tokens: tokens(&["t2", "t3", "t3"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::Ok, 3));
+        assert_eq!(h.upstream.requests().last(), Some(&tokens(&["t1", "t2", "t3"])));
+    }
+
+    #[tokio::test]
+    async fn clear_then_replay_is_an_error() {
+        let h = Harness::start(|_| 200).await;
+        let mut c = h.connect().await;
+        c.call(None, Command::Set { tokens: tokens(&["t1"]) }).await;
+        assert_eq!(c.call(None, Command::Clear).await.status, AckStatus::Ok);
+        let ack = c.call(None, Command::Replay).await;
+        assert_eq!(ack.status, AckStatus::Error);
+        assert!(ack.error.unwrap().contains("no last state"));
+        assert_eq!(h.upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stale_fence_is_rejected() {
+        let h = Harness::start(|_| 200).await;
+        let mut leader = h.connect().await;
+        assert_eq!(leader.call(Some(5), Command::Set { tokens: tokens(&["t1"]) }).await.status, AckStatus::Ok);
+
+        let mut old = h.connect().await;
+        let ack = old.call(Some(3), Command::Set { tokens: tokens(&["t2"]) }).await;
+        assert_eq!(ack.status, AckStatus::StaleFence);
+        // 被拒绝的指令不改变会话状态，也不触发抓取
+        let ack = leader.call(Some(5), Command::Replay).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::Ok, 1));
+        assert_eq!(h.upstream.requests(), vec![tokens(&["t1"]), tokens(&["t1"])]);
+    }
+
+    #[tokio::test]
+    async fn bisect_quarantines_only_the_bad_token() {
+        let h = Harness::start(|ts| if ts.iter().any(|t| t.starts_with("bad")) { 400 } else { 200 }).await;
+        let mut c = h.connect().await;
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t1", "t2", "bad3", "t4"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::Ok, 3));
+        let bad: Vec<_> = ack.quarantined.iter().map(|q| (q.token_id.as_str(), q.http_status)).collect();
+        assert_eq!(bad, vec![("bad3", Some(400))]);
+        assert_eq!(h.redis.calls("HMSET").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn bisect_does_not_quarantine_when_both_halves_fail() {
+        let h = Harness::start(|_| 404).await;
+        let mut c = h.connect().await;
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t1", "t2"]) }).await;
+        assert_eq!((ack.status, ack.http_status, ack.fetched), (AckStatus::Error, Some(404), 0));
+        assert!(ack.quarantined.is_empty());
+        assert!(h.redis.calls("HMSET").is_empty());
+    }
+
+    #[tokio::test]
+    async fn payload_too_large_is_passed_back_without_bisecting() {
+        let h = Harness::start(|_| 413).await;
+        let mut c = h.connect().await;
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t1", "t2", "t3", "t4"]) }).await;
+        assert_eq!((ack.status, ack.http_status), (AckStatus::Error, Some(413)));
+        assert_eq!(h.upstream.requests().len(), 1);
+    }
+
+    /// 与 `Upstream` 为 `token` 返回的快照同一时间戳的 WS 本地簿
+    fn ws_book(token: &str, bid_size: &str, hash: &str) -> LocalBook {
+        let ob: OrderBookSnapshot = serde_json::from_value(json!({
+            "market": "m",
+            "asset_id": token,
+            "hash": hash,
+            "timestamp": "1",
+            "bids": [{ "price": "0.5", "size": bid_size }],
+            "asks": [{ "price": "0.6", "size": "10" }],
+        }))
+        .unwrap();
+        LocalBook::from_snapshot(&ob)
+    }
+
+    #[tokio::test]
+    async fn divergent_ws_book_is_reported_and_repaired() {
+        let h = Harness::with_config(json!({ "ws": { "enabled": true } }), |_| 200).await;
+        let ws_books = h.node.ws_books.clone().unwrap();
+        ws_books.lock().unwrap().insert("t1".into(), ws_book("t1", "20", "h-t1"));
+        ws_books.lock().unwrap().insert("t2".into(), ws_book("t2", "10", "ws:1"));
+
+        let mut c = h.connect().await;
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t1", "t2"]) }).await;
+        assert_eq!((ack.status, ack.updated), (AckStatus::Ok, 2));
+
+        let events: Vec<serde_json::Value> = h
+            .redis
+            .calls("PUBLISH")
+            .iter()
+            .filter(|c| c[1] == "ob_divergence")
+            .map(|c| serde_json::from_str(&c[2]).unwrap())
+            .collect();
+        assert_eq!(events.len(), 1, "占位 hash 的 t2 不应报告分歧");
+        assert_eq!((events[0]["token_id"].as_str(), events[0]["kind"].as_str()), (Some("t1"), Some("levels")));
+        assert_eq!(h.redis.calls("HINCRBY").len(), 1);
+        // 写入脚本最后一个固定参数为 force：t1 强制修复，t2 照常 CAS
+        let force: Vec<_> = h.redis.calls("EVALSHA").iter().map(|c| (c[3].clone(), c[10].clone())).collect();
+        assert_eq!(force, vec![("ob:t1".to_string(), "1".to_string()), ("ob:t2".to_string(), "0".to_string())]);
+        assert_eq!(ws_books.lock().unwrap()["t1"].bids[0].size.to_string(), "10");
+    }
+
+    #[tokio::test]
+    async fn fetch_beyond_capacity_is_rate_limited() {
+        let h = Harness::with_config(json!({ "capacity_rps": 1 }), |_| 200).await;
+        let mut c = h.connect().await;
+        assert_eq!(c.call(None, Command::Set { tokens: tokens(&["t1"]) }).await.status, AckStatus::Ok);
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t2"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::RateLimited, 0));
+        // 不抓取的指令不占额度
+        assert_eq!(c.call(None, Command::Ping).await.status, AckStatus::Ok);
+        assert_eq!(h.upstream.requests(), vec![tokens(&["t1"])]);
+    }
+
+    #[tokio::test]
+    async fn expired_deadline_skips_upstream() {
+        let h = Harness::start(|_| 200).await;
+        let mut c = h.connect().await;
+        let ack = c.call_with_deadline(None, Some(0), Command::Set { tokens: tokens(&["t1"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::DeadlineExceeded, 0));
+        assert!(h.upstream.requests().is_empty());
+        assert!(h.redis.calls("EVALSHA").is_empty());
+    }
+
+    #[tokio::test]
+    async fn deadline_cancels_slow_upstream_mid_fetch() {
+        let upstream = Upstream::start_delayed(Duration::from_millis(2000), |_| 200).await;
+        let h = Harness::with_upstream(json!({}), upstream).await;
+        let mut c = h.connect().await;
+        let start = Instant::now();
+        let ack = c.call_with_deadline(None, Some(100), Command::Set { tokens: tokens(&["t1", "t2"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::DeadlineExceeded, 0));
+        assert!(start.elapsed() < Duration::from_millis(1000), "{:?}", start.elapsed());
+        assert_eq!(h.upstream.requests(), vec![tokens(&["t1", "t2"])]);
+        assert!(h.redis.calls("EVALSHA").is_empty());
+    }
+
+    #[tokio::test]
+    async fn heartbeat_registers_with_ttl_and_refreshes() {
+        let redis = MockRedis::start().await;
+        let cfg: FetchConfig = serde_json::from_value(json!({
+            "redis_url": redis.url,
+            "base_url": "http://127.0.0.1:1",
+            "node_id": "n1",
+            "capacity_rps": 40,
+            "heartbeat_ms": 100,
+            "node_ttl_ms": 300,
+        }))
+        .unwrap();
+        let client = RedisClient::connect(&redis.url).await.unwrap();
+        let task = tokio::spawn(heartbeat_loop(cfg, "10.0.0.1:9000".into(), client));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        task.abort();
+
+        let sets = redis.calls("SET");
+        assert!(sets.len() >= 2, "{:?}", sets);
+        let mut beats = Vec::new();
+        for set in &sets {
+            assert_eq!((set[1].as_str(), set[3].as_str(), set[4].as_str()), ("node:n1", "PX", "300"));
+            let rec: NodeRecord = serde_json::from_str(&set[2]).unwrap();
+            assert_eq!((rec.addr.as_str(), rec.capacity_rps), ("10.0.0.1:9000", 40));
+            beats.push((rec.started_at_ms, rec.heartbeat_ms));
+        }
+        assert!(beats.windows(2).all(|w| w[0].0 == w[1].0 && w[0].1 < w[1].1), "{:?}", beats);
+        assert!(redis.calls("SADD").iter().all(|c| c[1..] == ["nodes", "n1"]));
+    }
+
+    #[tokio::test]
+    async fn ping_and_hello_do_not_touch_upstream() {
+        let h = Harness::start(|_| 200).await;
+        let mut c = h.connect().await;
+        let ack = c.call(Some(7), Command::Ping).await;
+        assert_eq!(ack.status, AckStatus::Ok);
+        assert!(ack.node.is_none() && ack.report.is_none() && ack.error.is_none());
+        let info = c.call(None, Command::Hello).await.node.unwrap();
+        assert_eq!((info.node_id.as_str(), info.capacity_rps), ("test", 1000));
+        assert!(h.upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn status_reports_redis_upstream_and_rate() {
+        let h = Harness::with_config(json!({ "capacity_rps": 10 }), |_| 404).await;
+        let mut c = h.connect().await;
+        let report = c.call(None, Command::Status).await.report.unwrap();
+        assert_eq!((report.node_id.as_str(), report.capacity_rps), ("test", 10));
+        assert!(report.redis_ok);
+        assert!(report.last_upstream.is_none());
+        assert_eq!((report.in_flight, report.current_rps), (0, 0));
+
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t1"]) }).await;
+        assert_eq!(ack.http_status, Some(404));
+        let report = c.call(None, Command::Status).await.report.unwrap();
+        let upstream = report.last_upstream.unwrap();
+        assert_eq!(upstream.status, Some(404));
+        assert!(upstream.error.unwrap().contains("404"));
+        assert_eq!((report.in_flight, report.current_rps), (0, 1));
+        assert!((report.rate_usage - 0.1).abs() < 1e-9);
+        assert!(!h.redis.calls("PING").is_empty());
+    }
+
+    #[tokio::test]
+    async fn ack_lists_only_tokens_whose_book_changed() {
+        let h = Harness::start(|_| 200).await;
+        h.redis.unchanged(&["t2"]);
+        let mut c = h.connect().await;
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t1", "t2", "t3"]) }).await;
+        assert_eq!((ack.status, ack.fetched, ack.updated), (AckStatus::Ok, 3, 2));
+        assert_eq!(ack.changed, tokens(&["t1", "t3"]));
+        assert_eq!(h.redis.calls("PUBLISH").len(), 2);
+    }
+
+    /// `/books` 不重试，失败后立即进入回退判断
+    fn fallback_config(enabled: bool) -> serde_json::Value {
+        json!({
+            "retry": { "max_attempts": 1 },
+            "fallback": { "enabled": enabled, "tokens": ["t1", "t3"] },
+        })
+    }
+
+    #[tokio::test]
+    async fn fallback_fetches_key_tokens_after_books_fails() {
+        let h = Harness::with_config(fallback_config(true), |_| 503).await;
+        let mut c = h.connect().await;
+        let ack = c.call(None, Command::Set { tokens: tokens(&["t1", "t2", "t3"]) }).await;
+        assert_eq!((ack.status, ack.http_status, ack.fetched, ack.updated), (AckStatus::Error, Some(503), 2, 2));
+        let mut singles = h.upstream.single_requests();
+        singles.sort();
+        assert_eq!(singles, tokens(&["t1", "t3"]));
+        assert_eq!(h.redis.calls("EVALSHA").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fallback_is_skipped_when_disabled_or_not_applicable() {
+        // 未开启
+        let h = Harness::with_config(fallback_config(false), |_| 503).await;
+        let ack = h.connect().await.call(None, Command::Set { tokens: tokens(&["t1"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::Error, 0));
+        assert!(h.upstream.single_requests().is_empty());
+
+        // /books 成功
+        let h = Harness::with_config(fallback_config(true), |_| 200).await;
+        let ack = h.connect().await.call(None, Command::Set { tokens: tokens(&["t1", "t2"]) }).await;
+        assert_eq!((ack.status, ack.fetched), (AckStatus::Ok, 2));
+        assert!(h.upstream.single_requests().is_empty());
+
+        // 4xx 交给对分；429 默认不回退
+        for status in [404, 429] {
+            let h = Harness::with_config(fallback_config(true), move |_| status).await;
+            let ack = h.connect().await.call(None, Command::Set { tokens: tokens(&["t1"]) }).await;
+            assert_eq!((ack.http_status, ack.fetched), (Some(status), 0));
+            assert!(h.upstream.single_requests().is_empty(), "{}", status);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use poly_ob_common::book::LocalBook;
+use poly_ob_common::settings::ReconcileConfig;
+use poly_ob_common::types::{DivergenceKind, OrderBookSnapshot};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// WS 接入维护的本地簿，与对账共享；断线重连时清空
+pub type SharedBooks = Arc<Mutex<HashMap<String, LocalBook>>>;
+
+/// 一次对账发现的分歧（尚未计数）
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub kind: DivergenceKind,
+    pub ws_hash: String,
+    pub ws_timestamp: String,
+}
+
+/// 以 REST 快照为准核对 WS 本地簿：时间戳相同时比较 hash 与价位（本地 hash 为占位值时只比较价位），
+/// REST 领先超过 `lag_ms` 时比较价位；
+/// WS 领先的快照无法判断，跳过。发现分歧即以 REST 快照替换本地簿，后续增量在正确的基础上应用
+/// （Redis 副本由随后的 REST CAS 写入修复）
+pub fn reconcile(cfg: &ReconcileConfig, books: &SharedBooks, rest: &OrderBookSnapshot) -> Option<Divergence> {
+    let mut books = books.lock().unwrap();
+    let ws = books.get_mut(&rest.asset_id)?;
+    // 补全 WS 快照缺少的字段，供增量后重算 hash
+    ws.min_order_size = ws.min_order_size.or(rest.min_order_size);
+    ws.neg_risk = ws.neg_risk.or(rest.neg_risk);
+    ws.tick_size = ws.tick_size.or(rest.tick_size);
+    let (ws_ts, rest_ts) = (ws.timestamp.parse::<i64>().ok()?, rest.timestamp.parse::<i64>().ok()?);
+    let same_levels = ws.same_levels(rest);
+    let kind = if ws_ts == rest_ts {
+        let same_hash = ws.has_synthetic_hash() || ws.hash == rest.hash;
+        match (same_hash, same_levels) {
+            (true, true) => return None,
+            (false, true) => DivergenceKind::Hash,
+            // hash 相同而价位不同：本地增量应用有误
+            (_, false) => DivergenceKind::Levels,
+        }
+    } else if rest_ts - ws_ts > cfg.lag_ms && !same_levels {
+        DivergenceKind::Lagging
+    } else {
+        return None;
+    };
+    let div = Divergence { kind, ws_hash: ws.hash.clone(), ws_timestamp: ws.timestamp.clone() };
+    *ws = LocalBook::from_snapshot(rest);
+    Some(div)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poly_ob_common::book::{LevelDelta, Side};
+
+    fn rest() -> OrderBookSnapshot {
+        serde_json::from_value(serde_json::json!({
+            "market": "m",
+            "asset_id": "t1",
+            "hash": "h-t1",
+            "timestamp": "100",
+            "bids": [{ "price": "0.5", "size": "10" }],
+            "asks": [{ "price": "0.6", "size": "10" }],
+        }))
+        .unwrap()
+    }
+
+    fn shared(book: LocalBook) -> SharedBooks {
+        Arc::new(Mutex::new(HashMap::from([(book.asset_id.clone(), book)])))
+    }
+
+    #[test]
+    fn equal_books_do_not_diverge() {
+        let books = shared(LocalBook::from_snapshot(&rest()));
+        assert!(reconcile(&ReconcileConfig::default(), &books, &rest()).is_none());
+    }
+
+    #[test]
+    fn level_mismatch_diverges_and_replaces_local_book() {
+        let mut ws = LocalBook::from_snapshot(&rest());
+        ws.bids[0].size = "20".parse().unwrap();
+        let books = shared(ws);
+        let d = reconcile(&ReconcileConfig::default(), &books, &rest()).unwrap();
+        assert_eq!((d.kind, d.ws_hash.as_str(), d.ws_timestamp.as_str()), (DivergenceKind::Levels, "h-t1", "100"));
+        assert!(books.lock().unwrap()["t1"].same_levels(&rest()));
+    }
+
+    #[test]
+    fn hash_mismatch_with_equal_levels_is_a_hash_divergence() {
+        let mut ws = LocalBook::from_snapshot(&rest());
+        ws.hash = "other".into();
+        let d = reconcile(&ReconcileConfig::default(), &shared(ws), &rest()).unwrap();
+        assert_eq!(d.kind, DivergenceKind::Hash);
+    }
+
+    #[test]
+    fn synthetic_hash_from_deltas_is_not_compared() {
+        // 快照后收到一条不带 hash 的增量，本地 hash 变为占位值，簿面与同一时间戳的 REST 快照一致
+        let mut snapshot = rest();
+        snapshot.timestamp = "90".into();
+        snapshot.bids[0].size = "5".parse().unwrap();
+        let mut ws = LocalBook::from_snapshot(&snapshot);
+        let delta = LevelDelta {
+            side: Side::Buy,
+            price: "0.5".parse().unwrap(),
+            size: "10".parse().unwrap(),
+            hash: None,
+            best_bid: None,
+            best_ask: None,
+        };
+        assert_eq!(ws.apply_update("100", &[delta], false), Ok(true));
+        assert!(ws.has_synthetic_hash());
+        assert!(reconcile(&ReconcileConfig::default(), &shared(ws), &rest()).is_none());
+    }
+}
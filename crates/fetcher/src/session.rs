@@ -0,0 +1,80 @@
+use anyhow::Result;
+use poly_ob_common::protocol::Command;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+/// 节点级会话状态：所有连接共享同一份“上次 payload”，并记录见过的最大 fencing token
+#[derive(Debug, Default)]
+pub struct Session {
+    last: Vec<String>,
+    fence: u64,
+    /// 各 token 最近一次出现在抓取中的时刻，供 WS 接入确定订阅范围
+    assigned: HashMap<String, Instant>,
+}
+
+pub type SharedSession = Arc<Mutex<Session>>;
+
+impl Session {
+    pub fn shared() -> SharedSession {
+        Arc::new(Mutex::new(Self::default()))
+    }
+
+    /// 校验 fencing token：低于已见最大值的拒绝（返回已见值）；更高的视为新 leader，
+    /// 接受并清空上次 payload。未携带 token 的指令不受约束
+    pub fn check_fence(&mut self, fence: Option<u64>) -> Result<(), u64> {
+        let Some(f) = fence else { return Ok(()) };
+        if f < self.fence {
+            return Err(self.fence);
+        }
+        if f > self.fence {
+            self.fence = f;
+            self.last.clear();
+        }
+        Ok(())
+    }
+
+    /// 按指令更新状态，返回本次需要抓取的 tokens；`None` 表示无需抓取
+    pub fn apply(&mut self, cmd: &Command) -> Result<Option<Vec<String>>> {
+        match cmd {
+            Command::Set { tokens } if !tokens.is_empty() => {
+                self.last = tokens.clone();
+            }
+            Command::Set { .. } | Command::Replay => {}
+            Command::Append { tokens } => {
+                for t in tokens {
+                    if !self.last.contains(t) {
+                        self.last.push(t.clone());
+                    }
+                }
+            }
+            Command::Clear => {
+                self.last.clear();
+                return Ok(None);
+            }
+            Command::Hello | Command::Ping | Command::Status => return Ok(None),
+        }
+        if self.last.is_empty() {
+            anyhow::bail!("empty tokens payload and no last state");
+        }
+        let now = Instant::now();
+        for t in &self.last {
+            match self.assigned.get_mut(t) {
+                Some(at) => *at = now,
+                None => {
+                    self.assigned.insert(t.clone(), now);
+                }
+            }
+        }
+        Ok(Some(self.last.clone()))
+    }
+
+    /// 最近 `ttl` 内被分配过的 token（按字典序），顺带清理过期记录
+    pub fn assigned(&mut self, ttl: Duration) -> Vec<String> {
+        let now = Instant::now();
+        self.assigned.retain(|_, at| now.duration_since(*at) <= ttl);
+        let mut tokens: Vec<String> = self.assigned.keys().cloned().collect();
+        tokens.sort();
+        tokens
+    }
+}
@@ -0,0 +1,51 @@
+use poly_ob_common::protocol::UpstreamState;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+/// 节点运行统计，供 `status` 指令上报
+#[derive(Debug)]
+pub struct NodeStats {
+    started: Instant,
+    in_flight: AtomicUsize,
+    last_upstream: Mutex<Option<UpstreamState>>,
+}
+
+pub type SharedStats = Arc<NodeStats>;
+
+/// 在途批次计数守卫：创建时 +1，释放时 -1
+pub struct InFlight(SharedStats);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl NodeStats {
+    pub fn shared() -> SharedStats {
+        Arc::new(Self { started: Instant::now(), in_flight: AtomicUsize::new(0), last_upstream: Mutex::new(None) })
+    }
+
+    pub fn uptime_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn begin(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    pub fn record_upstream(&self, status: Option<u16>, error: Option<String>) {
+        let at_ms = chrono::Utc::now().timestamp_millis();
+        *self.last_upstream.lock().unwrap() = Some(UpstreamState { status, error, at_ms });
+    }
+
+    pub fn last_upstream(&self) -> Option<UpstreamState> {
+        self.last_upstream.lock().unwrap().clone()
+    }
+}
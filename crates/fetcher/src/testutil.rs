@@ -0,0 +1,195 @@
+//! 测试用的本地上游 `/books` 与最小 Redis（RESP）替身，均监听回环地址的随机端口
+
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::time::Duration;
+
+/// 上游替身：记录每次 `POST /books` 请求的 token 列表；`status` 决定该批的响应状态，
+/// 200 时为每个 token 返回一本最小订单簿，其余状态返回错误文本。`GET /book` 一律返回该 token 的订单簿
+pub struct Upstream {
+    pub base: String,
+    pub requests: Arc<Mutex<Vec<Vec<String>>>>,
+    singles: Arc<Mutex<Vec<String>>>,
+}
+
+impl Upstream {
+    pub async fn start(status: impl Fn(&[String]) -> u16 + Send + Sync + 'static) -> Self {
+        Self::start_delayed(Duration::ZERO, status).await
+    }
+
+    /// 每个请求记录后等待 `delay` 再响应，模拟慢上游
+    pub async fn start_delayed(delay: Duration, status: impl Fn(&[String]) -> u16 + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let (requests, singles) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let (log, single_log, status) = (requests.clone(), singles.clone(), Arc::new(status));
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let (log, single_log, status) = (log.clone(), single_log.clone(), status.clone());
+                tokio::spawn(async move {
+                    let (rd, mut wr) = sock.into_split();
+                    let mut rd = BufReader::new(rd);
+                    while let Some((target, body)) = read_http_request(&mut rd).await {
+                        if let Some(token) = target.strip_prefix("/book?token_id=") {
+                            single_log.lock().unwrap().push(token.to_string());
+                            let body = book(token).to_string();
+                            let resp = format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+                            if wr.write_all(resp.as_bytes()).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        let tokens: Vec<String> = serde_json::from_slice::<Vec<Value>>(&body)
+                            .unwrap_or_default()
+                            .iter()
+                            .filter_map(|v| v["token_id"].as_str().map(str::to_string))
+                            .collect();
+                        log.lock().unwrap().push(tokens.clone());
+                        tokio::time::sleep(delay).await;
+                        let code = status(&tokens);
+                        let body = match code {
+                            200 => Value::Array(tokens.iter().map(|t| book(t)).collect()).to_string(),
+                            _ => json!({ "error": format!("status {}", code) }).to_string(),
+                        };
+                        let resp = format!(
+                            "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            code,
+                            body.len(),
+                            body
+                        );
+                        if wr.write_all(resp.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        Self { base, requests, singles }
+    }
+
+    pub fn requests(&self) -> Vec<Vec<String>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// `GET /book` 请求的 token（按到达顺序）
+    pub fn single_requests(&self) -> Vec<String> {
+        self.singles.lock().unwrap().clone()
+    }
+}
+
+fn book(token: &str) -> Value {
+    json!({
+        "market": "m",
+        "asset_id": token,
+        "hash": format!("h-{}", token),
+        "timestamp": "1",
+        "bids": [{ "price": "0.5", "size": "10" }],
+        "asks": [{ "price": "0.6", "size": "10" }],
+    })
+}
+
+/// 读取一个 HTTP 请求并返回请求路径与请求体；连接关闭时为 `None`
+async fn read_http_request<R: AsyncRead + Unpin>(rd: &mut BufReader<R>) -> Option<(String, Vec<u8>)> {
+    let mut request = String::new();
+    if rd.read_line(&mut request).await.ok()? == 0 {
+        return None;
+    }
+    let target = request.split(' ').nth(1).unwrap_or_default().to_string();
+    let mut len = 0;
+    loop {
+        let mut line = String::new();
+        if rd.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.eq_ignore_ascii_case("content-length") {
+                len = v.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; len];
+    rd.read_exact(&mut body).await.ok()?;
+    Some((target, body))
+}
+
+/// Redis 替身：CAS 脚本对 [`MockRedis::unchanged`] 标记的 token 返回 `skip_hash`，其余返回 `updated`；
+/// 记录收到的命令
+pub struct MockRedis {
+    pub url: String,
+    pub commands: Arc<Mutex<Vec<Vec<String>>>>,
+    unchanged: Arc<Mutex<HashSet<String>>>,
+}
+
+impl MockRedis {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let unchanged = Arc::new(Mutex::new(HashSet::new()));
+        let (log, skip) = (commands.clone(), unchanged.clone());
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let (log, skip) = (log.clone(), skip.clone());
+                tokio::spawn(async move {
+                    let (rd, mut wr) = sock.into_split();
+                    let mut rd = BufReader::new(rd);
+                    while let Some(args) = read_resp_command(&mut rd).await {
+                        let reply: &[u8] = match args.first().map(|c| c.to_ascii_uppercase()).as_deref() {
+                            // EVALSHA sha numkeys key ...
+                            Some("EVALSHA" | "EVAL") if args.get(3).is_some_and(|k| skip.lock().unwrap().contains(k)) => {
+                                b"$9\r\nskip_hash\r\n"
+                            }
+                            Some("EVALSHA" | "EVAL") => b"$7\r\nupdated\r\n",
+                            Some("PING") => b"+PONG\r\n",
+                            Some("PUBLISH" | "HINCRBY") => b":1\r\n",
+                            Some("HVALS" | "SMEMBERS") => b"*0\r\n",
+                            _ => b"+OK\r\n",
+                        };
+                        log.lock().unwrap().push(args);
+                        if wr.write_all(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        Self { url, commands, unchanged }
+    }
+
+    /// 这些 token 的快照视为与已存副本相同（CAS 返回 `skip_hash`）
+    pub fn unchanged(&self, tokens: &[&str]) {
+        self.unchanged.lock().unwrap().extend(tokens.iter().map(|t| format!("ob:{}", t)));
+    }
+
+    /// 收到的某命令（如 `HMSET`）的全部参数
+    pub fn calls(&self, name: &str) -> Vec<Vec<String>> {
+        self.commands.lock().unwrap().iter().filter(|c| c[0].eq_ignore_ascii_case(name)).cloned().collect()
+    }
+}
+
+/// 读取一条 RESP 数组命令；连接关闭时为 `None`
+async fn read_resp_command<R: AsyncRead + Unpin>(rd: &mut BufReader<R>) -> Option<Vec<String>> {
+    let mut line = String::new();
+    if rd.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    let n: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(n);
+    for _ in 0..n {
+        line.clear();
+        rd.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut buf = vec![0; len + 2];
+        rd.read_exact(&mut buf).await.ok()?;
+        buf.truncate(len);
+        args.push(String::from_utf8_lossy(&buf).into_owned());
+    }
+    Some(args)
+}
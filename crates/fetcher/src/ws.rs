@@ -0,0 +1,428 @@
+use crate::reconcile::SharedBooks;
+use crate::session::SharedSession;
+use anyhow::Result;
+use futures_util::{SinkExt, StreamExt};
+use poly_ob_common::analytics::BookStats;
+use poly_ob_common::book::{LevelDelta, LocalBook, Side};
+use poly_ob_common::redisx::{CasOutcome, RedisClient};
+use poly_ob_common::settings::{AnalyticsConfig, WsConfig};
+use poly_ob_common::decimal::Decimal;
+use poly_ob_common::types::{de_asks, de_bids, BookLevel, OrderBookSnapshot};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::{debug, info, warn};
+
+/// market 频道事件；其余类型（`tick_size_change`、`last_trade_price` 等）忽略
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event_type", rename_all = "snake_case")]
+enum MarketEvent {
+    Book(WsBook),
+    PriceChange(WsPriceChange),
+    #[serde(other)]
+    Other,
+}
+
+/// 全量快照：订阅成功时每个 token 推送一次，之后在成交等导致簿面重置时推送
+#[derive(Debug, Deserialize)]
+struct WsBook {
+    asset_id: String,
+    market: String,
+    timestamp: String,
+    hash: String,
+    #[serde(alias = "buys", deserialize_with = "de_bids")]
+    bids: Vec<BookLevel>,
+    #[serde(alias = "sells", deserialize_with = "de_asks")]
+    asks: Vec<BookLevel>,
+}
+
+/// 价位增量。新格式按 token 列在 `price_changes` 中并各带 hash；旧格式整条消息对应一个 token
+#[derive(Debug, Deserialize)]
+struct WsPriceChange {
+    timestamp: String,
+    #[serde(default)]
+    price_changes: Vec<WsLevelChange>,
+    #[serde(default)]
+    asset_id: Option<String>,
+    #[serde(default)]
+    changes: Vec<WsLevelChange>,
+    #[serde(default)]
+    hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsLevelChange {
+    #[serde(default)]
+    asset_id: Option<String>,
+    price: Decimal,
+    size: Decimal,
+    side: String,
+    #[serde(default)]
+    hash: Option<String>,
+    #[serde(default)]
+    best_bid: Option<Decimal>,
+    #[serde(default)]
+    best_ask: Option<Decimal>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    books: u64,
+    changes: u64,
+    updated: u64,
+    /// 尚未收到快照的 token 的增量，无法应用
+    orphaned: u64,
+    /// 增量校验失败、需重新订阅取快照的次数
+    gaps: u64,
+}
+
+/// WS 接入主循环：按本节点的分配维护订阅，断线后等待 `reconnect_ms` 重连（重连后服务端重新推送快照）。
+/// 本地簿写入 `books`，供 REST 抓取时对账
+pub async fn ws_loop(cfg: WsConfig, analytics: AnalyticsConfig, mut redis: RedisClient, session: SharedSession, books: SharedBooks) {
+    info!(url = %cfg.url, static_tokens = cfg.tokens.len(), "ws ingestion enabled");
+    loop {
+        let tokens = wanted(&cfg, &session);
+        if tokens.is_empty() {
+            tokio::time::sleep(Duration::from_millis(cfg.resubscribe_ms.max(100))).await;
+            continue;
+        }
+        match run_conn(&cfg, &analytics, &mut redis, &session, &books, tokens).await {
+            Ok(()) => warn!("ws closed by server, reconnecting"),
+            Err(e) => warn!("ws error: {}, reconnecting", e),
+        }
+        books.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_millis(cfg.reconnect_ms)).await;
+    }
+}
+
+/// 固定订阅与近期分配的并集
+fn wanted(cfg: &WsConfig, session: &SharedSession) -> HashSet<String> {
+    let assigned = session.lock().unwrap().assigned(Duration::from_millis(cfg.assign_ttl_ms));
+    cfg.tokens.iter().cloned().chain(assigned).collect()
+}
+
+async fn run_conn(
+    cfg: &WsConfig,
+    analytics: &AnalyticsConfig,
+    redis: &mut RedisClient,
+    session: &SharedSession,
+    books: &SharedBooks,
+    mut subscribed: HashSet<String>,
+) -> Result<()> {
+    let (ws, _) = connect_async(cfg.url.as_str()).await?;
+    let (mut write, mut read) = ws.split();
+    let ids: Vec<&String> = subscribed.iter().collect();
+    write.send(Message::Text(serde_json::json!({ "type": "market", "assets_ids": ids }).to_string())).await?;
+    info!(tokens = subscribed.len(), "ws subscribed");
+
+    let mut counters = Counters::default();
+    let mut ping = tokio::time::interval(Duration::from_secs(cfg.ping_secs.max(1)));
+    let mut resubscribe = tokio::time::interval(Duration::from_millis(cfg.resubscribe_ms.max(100)));
+    resubscribe.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut report = tokio::time::interval_at(Instant::now() + Duration::from_secs(60), Duration::from_secs(60));
+    loop {
+        tokio::select! {
+            msg = read.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(t))) => t,
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                if text == "PONG" {
+                    continue;
+                }
+                let (updates, resync) = apply_message(&text, &mut books.lock().unwrap(), cfg.verify_hash, &mut counters);
+                if !resync.is_empty() {
+                    // 退订再订阅，服务端随即推送这些 token 的全量快照
+                    write.send(Message::Text(serde_json::json!({ "operation": "unsubscribe", "assets_ids": resync }).to_string())).await?;
+                    write.send(Message::Text(serde_json::json!({ "operation": "subscribe", "assets_ids": resync }).to_string())).await?;
+                }
+                for ob in updates {
+                    let now_ms = chrono::Utc::now().timestamp_millis();
+                    let stats = BookStats::configured(analytics, &ob);
+                    if redis.cas_upsert_book(&ob, now_ms, stats.as_ref()).await? == CasOutcome::Updated {
+                        counters.updated += 1;
+                        let _ = redis.publish_update("ob_updates", &ob).await;
+                    }
+                }
+            }
+            _ = ping.tick() => {
+                write.send(Message::Text("PING".into())).await?;
+            }
+            _ = resubscribe.tick() => {
+                let want = wanted(cfg, session);
+                let added: Vec<&String> = want.difference(&subscribed).collect();
+                let removed: Vec<&String> = subscribed.difference(&want).collect();
+                if !added.is_empty() {
+                    write.send(Message::Text(serde_json::json!({ "operation": "subscribe", "assets_ids": added }).to_string())).await?;
+                }
+                if !removed.is_empty() {
+                    write.send(Message::Text(serde_json::json!({ "operation": "unsubscribe", "assets_ids": removed }).to_string())).await?;
+                    let mut books = books.lock().unwrap();
+                    for t in &removed {
+                        books.remove(*t);
+                    }
+                }
+                if !added.is_empty() || !removed.is_empty() {
+                    info!(added = added.len(), removed = removed.len(), total = want.len(), "ws subscription changed");
+                    subscribed = want;
+                }
+            }
+            _ = report.tick() => {
+                info!(
+                    books = counters.books, changes = counters.changes, updated = counters.updated,
+                    orphaned = counters.orphaned, gaps = counters.gaps, "ws ingestion in the last minute"
+                );
+                counters = Counters::default();
+            }
+        }
+    }
+}
+
+/// 解析一条消息（单个事件或事件数组）并应用到本地簿，返回需要写入的快照与出现缺口、需重新取快照的 token
+fn apply_message(
+    text: &str,
+    books: &mut HashMap<String, LocalBook>,
+    verify_hash: bool,
+    counters: &mut Counters,
+) -> (Vec<OrderBookSnapshot>, Vec<String>) {
+    let events: Vec<MarketEvent> = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(serde_json::Value::Array(arr)) => arr.into_iter().filter_map(|v| serde_json::from_value(v).ok()).collect(),
+        Ok(v) => serde_json::from_value(v).into_iter().collect(),
+        Err(e) => {
+            debug!("ws non-json message: {}", e);
+            return (Vec::new(), Vec::new());
+        }
+    };
+    let (mut out, mut resync) = (Vec::new(), Vec::new());
+    for ev in events {
+        match ev {
+            MarketEvent::Book(b) => {
+                counters.books += 1;
+                // WS 快照不含 min_order_size 等字段，沿用对账时从 REST 补全的值
+                let prev = books.remove(&b.asset_id);
+                let book = LocalBook {
+                    market: b.market,
+                    asset_id: b.asset_id.clone(),
+                    timestamp: b.timestamp,
+                    hash: b.hash,
+                    bids: b.bids,
+                    asks: b.asks,
+                    min_order_size: prev.as_ref().and_then(|p| p.min_order_size),
+                    neg_risk: prev.as_ref().and_then(|p| p.neg_risk),
+                    tick_size: prev.as_ref().and_then(|p| p.tick_size),
+                };
+                out.push(book.to_snapshot());
+                books.insert(b.asset_id, book);
+            }
+            MarketEvent::PriceChange(pc) => {
+                counters.changes += 1;
+                // 按 token 归组，保持消息内顺序
+                let mut grouped: Vec<(&str, Vec<LevelDelta>)> = Vec::new();
+                for c in pc.price_changes.iter().chain(&pc.changes) {
+                    let Some(asset) = c.asset_id.as_deref().or(pc.asset_id.as_deref()) else { continue };
+                    let side = match c.side.as_str() {
+                        "BUY" => Side::Buy,
+                        "SELL" => Side::Sell,
+                        other => {
+                            debug!(side = other, "unknown price_change side");
+                            continue;
+                        }
+                    };
+                    let delta = LevelDelta {
+                        side,
+                        price: c.price,
+                        size: c.size,
+                        hash: c.hash.clone().or_else(|| pc.hash.clone()),
+                        best_bid: c.best_bid,
+                        best_ask: c.best_ask,
+                    };
+                    match grouped.iter_mut().find(|(a, _)| *a == asset) {
+                        Some((_, deltas)) => deltas.push(delta),
+                        None => grouped.push((asset, vec![delta])),
+                    }
+                }
+                for (asset, deltas) in grouped {
+                    let Some(book) = books.get_mut(asset) else {
+                        counters.orphaned += deltas.len() as u64;
+                        continue;
+                    };
+                    match book.apply_update(&pc.timestamp, &deltas, verify_hash) {
+                        Ok(true) => out.push(book.to_snapshot()),
+                        Ok(false) => {}
+                        Err(gap) => {
+                            warn!(token = asset, "ws book gap: {}, resyncing", gap);
+                            counters.gaps += 1;
+                            books.remove(asset);
+                            resync.push(asset.to_string());
+                        }
+                    }
+                }
+            }
+            MarketEvent::Other => {}
+        }
+    }
+    (out, resync)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "65818619657568813474341868652308942079804919287380422192892211131408793125422";
+    const B: &str = "52114319501245915516055106046884209969926127482827954674443846427813813222426";
+
+    // 以下消息沿用 market 频道推送的字段与格式（取自文档示例），价位与 hash 为构造值
+    const BOOK_A: &str = r#"[{"market":"0x5f65177b394277fd294cd75650044e32ba009a95022d88a0c1d565897d72f8f1","asset_id":"65818619657568813474341868652308942079804919287380422192892211131408793125422","timestamp":"1757908892351","hash":"0x0b3f6e9a7c1d2e4f5a6b7c8d9e0f1a2b3c4d5e6f","bids":[{"price":"0.48","size":"30"},{"price":"0.49","size":"20"},{"price":"0.47","size":"100"}],"asks":[{"price":"0.52","size":"25"},{"price":"0.53","size":"60"},{"price":"0.51","size":"10"}],"event_type":"book"}]"#;
+    const PRICE_CHANGE: &str = r#"{"market":"0x5f65177b394277fd294cd75650044e32ba009a95022d88a0c1d565897d72f8f1","price_changes":[{"asset_id":"65818619657568813474341868652308942079804919287380422192892211131408793125422","price":"0.5","size":"200","side":"BUY","hash":"56621a121a47ed9333273e21c83b660cff37ae50","best_bid":"0.5","best_ask":"0.51"},{"asset_id":"52114319501245915516055106046884209969926127482827954674443846427813813222426","price":"0.5","size":"200","side":"SELL","hash":"1895759e4df7a796bf4f1c5a5950b748306923e2","best_bid":"0","best_ask":"0.5"}],"timestamp":"1757908892360","event_type":"price_change"}"#;
+    const LEGACY_CHANGE: &str = r#"{"asset_id":"65818619657568813474341868652308942079804919287380422192892211131408793125422","changes":[{"price":"0.51","side":"SELL","size":"0"},{"price":"0.53","side":"SELL","size":"75"}],"event_type":"price_change","hash":"0x9e2f","market":"0x5f65177b394277fd294cd75650044e32ba009a95022d88a0c1d565897d72f8f1","timestamp":"1757908892370"}"#;
+    const OTHER: &str = r#"[{"asset_id":"65818619657568813474341868652308942079804919287380422192892211131408793125422","event_type":"tick_size_change","old_tick_size":"0.01","new_tick_size":"0.001","market":"0x5f65","timestamp":"1757908892380"},{"asset_id":"65818619657568813474341868652308942079804919287380422192892211131408793125422","event_type":"last_trade_price","price":"0.5","side":"BUY","size":"10","fee_rate_bps":"0","market":"0x5f65","timestamp":"1757908892381"}]"#;
+
+    fn prices(levels: &[BookLevel]) -> Vec<String> {
+        levels.iter().map(|l| l.price.to_string()).collect()
+    }
+
+    fn loaded() -> (HashMap<String, LocalBook>, Counters) {
+        let (mut books, mut counters) = (HashMap::new(), Counters::default());
+        let (out, resync) = apply_message(BOOK_A, &mut books, false, &mut counters);
+        assert_eq!((out.len(), resync.len(), counters.books), (1, 0, 1));
+        (books, counters)
+    }
+
+    #[test]
+    fn book_event_is_sorted_like_rest() {
+        let (books, _) = loaded();
+        let book = &books[A];
+        assert_eq!(prices(&book.bids), ["0.47", "0.48", "0.49"]);
+        assert_eq!(prices(&book.asks), ["0.53", "0.52", "0.51"]);
+        assert_eq!(book.timestamp, "1757908892351");
+    }
+
+    #[test]
+    fn price_change_applies_per_token_and_counts_orphans() {
+        let (mut books, mut counters) = loaded();
+        let (out, resync) = apply_message(PRICE_CHANGE, &mut books, false, &mut counters);
+        assert!(resync.is_empty());
+        assert_eq!(out.len(), 1);
+        let ob = &out[0];
+        assert_eq!((ob.asset_id.as_str(), ob.timestamp.as_str()), (A, "1757908892360"));
+        assert_eq!(ob.hash, "56621a121a47ed9333273e21c83b660cff37ae50");
+        assert_eq!(prices(&ob.bids), ["0.47", "0.48", "0.49", "0.5"]);
+        assert_eq!((counters.changes, counters.orphaned), (1, 1));
+        assert!(!books.contains_key(B));
+    }
+
+    #[test]
+    fn legacy_price_change_uses_message_asset_and_hash() {
+        let (mut books, mut counters) = loaded();
+        let (out, _) = apply_message(LEGACY_CHANGE, &mut books, false, &mut counters);
+        assert_eq!(out.len(), 1);
+        assert_eq!(prices(&out[0].asks), ["0.53", "0.52"]);
+        assert_eq!(out[0].asks[0].size.to_string(), "75");
+        assert_eq!(out[0].hash, "0x9e2f");
+    }
+
+    #[test]
+    fn best_price_mismatch_drops_book_and_requests_resync() {
+        let (mut books, mut counters) = loaded();
+        let mismatched = PRICE_CHANGE.replace(r#""best_bid":"0.5","best_ask":"0.51""#, r#""best_bid":"0.49","best_ask":"0.51""#);
+        let (out, resync) = apply_message(&mismatched, &mut books, false, &mut counters);
+        assert!(out.is_empty());
+        assert_eq!(resync, [A]);
+        assert_eq!(counters.gaps, 1);
+        assert!(!books.contains_key(A));
+    }
+
+    #[test]
+    fn stale_change_after_newer_book_is_skipped() {
+        let (mut books, mut counters) = loaded();
+        let newer = BOOK_A.replace("1757908892351", "1757908892400");
+        apply_message(&newer, &mut books, false, &mut counters);
+        let (out, resync) = apply_message(PRICE_CHANGE, &mut books, false, &mut counters);
+        assert_eq!((out.len(), resync.len()), (0, 0));
+        assert_eq!(books[A].timestamp, "1757908892400");
+    }
+
+    #[test]
+    fn ws_book_keeps_rest_only_fields() {
+        let (mut books, mut counters) = loaded();
+        let book = books.get_mut(A).unwrap();
+        book.tick_size = Some("0.01".parse().unwrap());
+        book.neg_risk = Some(false);
+        apply_message(BOOK_A, &mut books, false, &mut counters);
+        assert_eq!((books[A].tick_size, books[A].neg_risk), (Some("0.01".parse().unwrap()), Some(false)));
+    }
+
+    #[test]
+    fn other_events_and_non_json_are_ignored() {
+        let (mut books, mut counters) = loaded();
+        let before = books[A].clone();
+        for msg in [OTHER, "PONG", "[]"] {
+            let (out, resync) = apply_message(msg, &mut books, true, &mut counters);
+            assert_eq!((out.len(), resync.len()), (0, 0), "{}", msg);
+        }
+        assert_eq!(books[A], before);
+    }
+
+    /// 读取下一条 JSON 文本帧（跳过 PING）
+    async fn next_json<S>(ws: &mut tokio_tungstenite::WebSocketStream<S>) -> serde_json::Value
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Text(t))) if t == "PING" => continue,
+                Some(Ok(Message::Text(t))) => return serde_json::from_str(&t).unwrap(),
+                other => panic!("unexpected ws frame: {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_loop_writes_through_and_resubscribes_after_disconnect() {
+        let redis = crate::testutil::MockRedis::start().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let cfg = WsConfig {
+            url: format!("ws://{}", listener.local_addr().unwrap()),
+            tokens: vec![A.to_string()],
+            reconnect_ms: 10,
+            ..Default::default()
+        };
+        let books = SharedBooks::default();
+        let client = RedisClient::connect(&redis.url).await.unwrap();
+        tokio::spawn(ws_loop(cfg, AnalyticsConfig::default(), client, crate::session::Session::shared(), books.clone()));
+
+        let (sock, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(sock).await.unwrap();
+        assert_eq!(next_json(&mut ws).await, serde_json::json!({ "type": "market", "assets_ids": [A] }));
+        ws.send(Message::Text(BOOK_A.into())).await.unwrap();
+        ws.send(Message::Text(PRICE_CHANGE.into())).await.unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while redis.calls("EVALSHA").len() < 2 {
+            assert!(Instant::now() < deadline, "books not written through");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // EVALSHA sha numkeys key hash timestamp ...
+        let written: Vec<_> = redis.calls("EVALSHA").iter().map(|c| (c[3].clone(), c[4].clone(), c[5].clone())).collect();
+        let key = format!("ob:{}", A);
+        assert_eq!(
+            written,
+            vec![
+                (key.clone(), "0x0b3f6e9a7c1d2e4f5a6b7c8d9e0f1a2b3c4d5e6f".to_string(), "1757908892351".to_string()),
+                (key, "56621a121a47ed9333273e21c83b660cff37ae50".to_string(), "1757908892360".to_string()),
+            ]
+        );
+        assert_eq!(redis.calls("PUBLISH").len(), 2);
+        assert_eq!(books.lock().unwrap()[A].timestamp, "1757908892360");
+
+        // 断线后清空本地簿，重连并重新订阅
+        drop(ws);
+        let (sock, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+        let mut ws = tokio_tungstenite::accept_async(sock).await.unwrap();
+        assert_eq!(next_json(&mut ws).await, serde_json::json!({ "type": "market", "assets_ids": [A] }));
+        assert!(books.lock().unwrap().is_empty());
+    }
+}
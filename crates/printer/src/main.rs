@@ -22,8 +22,7 @@ async fn main() -> Result<()> {
     let args = Args::parse();
 
     let client = redis::Client::open(args.redis.clone())?;
-    let conn = client.get_async_connection().await?;
-    let mut pubsub = conn.into_pubsub();
+    let mut pubsub = client.get_async_pubsub().await?;
     pubsub.subscribe(&args.channel).await?;
 
     info!("printer subscribed to '{}' on {}", args.channel, args.redis);
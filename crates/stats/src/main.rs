@@ -10,7 +10,7 @@ struct Args {
     input: String,
 }
 
-fn percentile(sorted: &Vec<i128>, p: f64) -> i128 {
+fn percentile(sorted: &[i128], p: f64) -> i128 {
     if sorted.is_empty() { return 0; }
     let idx = ((sorted.len() as f64 - 1.0) * p).round() as usize;
     sorted[idx]
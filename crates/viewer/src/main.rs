@@ -39,9 +39,7 @@ async fn main() -> Result<()> {
 
 async fn subscribe_redis(url: &str, channel: &str, tx: broadcast::Sender<String>) -> Result<()> {
     let client = redis::Client::open(url)?;
-    // use simple async connection for PubSub (into_pubsub is on Connection)
-    let conn = client.get_async_connection().await?;
-    let mut pubsub = conn.into_pubsub();
+    let mut pubsub = client.get_async_pubsub().await?;
     pubsub.subscribe(channel).await?;
     loop {
         let msg: redis::Msg = pubsub.on_message().next().await.unwrap();
@@ -54,13 +52,8 @@ async fn sse_handler(axum::extract::State(tx): axum::extract::State<broadcast::S
     use axum::response::sse::{Event, Sse};
     let mut rx = tx.subscribe();
     let stream = async_stream::stream! {
-        loop {
-            match rx.recv().await {
-                Ok(msg) => {
-                    yield Ok(Event::default().data(msg));
-                }
-                Err(_) => break,
-            }
+        while let Ok(msg) = rx.recv().await {
+            yield Ok(Event::default().data(msg));
         }
     };
     Sse::new(stream)